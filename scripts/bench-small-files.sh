@@ -0,0 +1,56 @@
+#!/usr/bin/env bash
+# Upload many small files and print the throughput of each cymo build.
+#
+# Usage: scripts/bench-small-files.sh <server> <remote-path> [cymo binary...]
+#
+# The remote path is created and overwritten every round. Tune with FILES (default
+# 2000), SIZE in bytes (default 4096), THREADS (default 16) and ROUNDS (default 3),
+# other flags like credentials go into CYMO_FLAGS:
+#
+#   CYMO_FLAGS="-u anonymous -p x" scripts/bench-small-files.sh 127.0.0.1:2121 /bench \
+#       ./cymo-before target/release/cymo
+
+set -euo pipefail
+
+if [ $# -lt 2 ]; then
+    sed -n '3,4p' "$0" | cut -c3-
+    exit 1
+fi
+server=$1
+remote=$2
+shift 2
+binaries=("$@")
+if [ ${#binaries[@]} -eq 0 ]; then
+    binaries=(target/release/cymo)
+fi
+files=${FILES:-2000}
+size=${SIZE:-4096}
+threads=${THREADS:-16}
+rounds=${ROUNDS:-3}
+# Intentionally split, flags are separate words.
+read -r -a flags <<<"${CYMO_FLAGS:-}"
+
+local_path=$(mktemp -d)
+trap 'rm -rf "$local_path"' EXIT
+echo "Creating $files files of $size bytes in $local_path"
+for i in $(seq 1 "$files"); do
+    dir="$local_path/d$((i % 20))"
+    mkdir -p "$dir"
+    head -c "$size" /dev/urandom >"$dir/f$i"
+done
+
+"${binaries[0]}" mkdir -s "$server" "${flags[@]}" --parents "$remote" >/dev/null
+
+printf "%-40s %6s %9s %9s\n" binary round seconds files/s
+for binary in "${binaries[@]}"; do
+    for round in $(seq 1 "$rounds"); do
+        started=$(date +%s.%N)
+        "$binary" upload -s "$server" "${flags[@]}" -l "$local_path" -r "$remote" \
+            -t "$threads" >/dev/null
+        finished=$(date +%s.%N)
+        awk -v binary="$binary" -v round="$round" -v files="$files" \
+            -v started="$started" -v finished="$finished" \
+            'BEGIN { seconds = finished - started
+                     printf "%-40s %6d %9.2f %9.1f\n", binary, round, seconds, files / seconds }'
+    done
+done
@@ -1,9 +1,13 @@
-use clap::Parser;
+use chrono::NaiveTime;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use cymo::options::{self, Options, RateWindow};
+use serde::Deserialize;
+use std::path::PathBuf;
 
 /// Cymo: Multi-threaded FTP Upload Tool
 ///
-/// The `Args` struct represents the command-line arguments for the Cymo tool, which is used
-/// for multi-threaded FTP file uploads.
+/// The `Cli` struct represents the command-line arguments for the Cymo tool, each
+/// subcommand shares the same connection options.
 #[derive(Parser, Debug)]
 #[command(
     author,
@@ -15,41 +19,736 @@ You can specify the remote path on the FTP server, the local path to the files o
 
 Example Usage:
 To upload files to an FTP server, use a command like:
-$ cymo -r /ftp/upload -l /local/files -s ftp.example.com
+$ cymo upload -r /ftp/upload -l /local/files -s ftp.example.com
 
 Or use username and password for authentication:
-$ cymo -r /ftp/upload -l /local/files -s ftp.example.com -u <username> -p <password>"
+$ cymo upload -r /ftp/upload -l /local/files -s ftp.example.com -u <username> -p <password>"
 )]
-pub struct Args {
-    /// The remote path on the FTP server where files will be uploaded.
-    #[arg(short, long)]
-    pub remote_path: String,
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
 
-    /// The local path to the directory or file that will be uploaded to the FTP server.
-    #[arg(short, long)]
-    pub local_path: String,
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Upload local files and directories to the FTP server
+    Upload(Args),
+    /// Download a remote directory or file from the FTP server
+    Download(DownloadArgs),
+    /// Upload changed files and delete remote entries not in local
+    ///
+    /// Same as `upload --skip-existing mtime --delete`.
+    Sync(Args),
+    /// List a remote directory
+    Ls(LsArgs),
+    /// Remove remote files or directories
+    Rm(RmArgs),
+    /// Create remote directories
+    Mkdir(MkdirArgs),
+    /// Put the previous release of `upload --release` back in place
+    Rollback(RollbackArgs),
+}
+
+impl Command {
+    /// Connection options of the subcommand.
+    pub fn connection_mut(&mut self) -> &mut Connection {
+        match self {
+            Command::Upload(args) | Command::Sync(args) => &mut args.connection,
+            Command::Download(args) => &mut args.connection,
+            Command::Ls(args) => &mut args.connection,
+            Command::Rm(args) => &mut args.connection,
+            Command::Mkdir(args) => &mut args.connection,
+            Command::Rollback(args) => &mut args.connection,
+        }
+    }
+}
 
+/// Options to connect to the FTP server, shared by all subcommands.
+#[derive(ClapArgs, Clone, Debug)]
+pub struct Connection {
     /// The FTP server address or hostname where the files will be uploaded.
+    ///
+    /// May have a scheme and port, like `sftp://example.com:2222`.
     #[arg(short, long)]
     pub server: String,
 
+    /// Named profile in `./cymo.toml` or `~/.config/cymo/config.toml`, flags override its values
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Remote server port [default: 21, 990 with implicit TLS, 22 with SFTP]
+    #[arg(long)]
+    pub port: Option<u32>,
+
+    /// Protocol to connect with [default: scheme of `--server`, or ftp]
+    #[arg(long, value_enum)]
+    pub protocol: Option<Protocol>,
+
     /// The username for authenticating with the FTP server (optional).
     #[arg(short, long)]
     pub username: Option<String>,
 
     /// The password for authenticating with the FTP server (optional).
+    ///
+    /// Visible in process list and shell history, prefer `CYMO_PASSWORD`,
+    /// `--password-file` or `~/.netrc`.
     #[arg(short, long)]
     pub password: Option<String>,
 
-    /// Retry times
+    /// Read the password from the first line of a file
     #[arg(long)]
-    pub retry: Option<u32>,
+    pub password_file: Option<PathBuf>,
+
+    /// Private key for SFTP, `--password` is used as its passphrase when encrypted
+    #[arg(short, long)]
+    pub identity: Option<PathBuf>,
 
-    /// Remote server port
-    #[arg(long, default_value_t = 21)]
-    pub port: u32,
+    /// Secure the control and data connections with FTPS
+    #[arg(long, value_enum, default_value_t = TlsMode::None)]
+    pub tls: TlsMode,
+
+    /// PEM file with CA certificates used to verify the server
+    #[arg(long)]
+    pub ca_file: Option<PathBuf>,
+
+    /// Accept invalid or self-signed server certificates, or unknown SSH host keys
+    #[arg(long)]
+    pub insecure: bool,
 
     /// Specific thread numbers
     #[arg(short, long)]
     pub thread: Option<usize>,
+
+    /// Retry times
+    #[arg(long)]
+    pub retry: Option<u32>,
+
+    /// Seconds to wait before the first retry or reconnection
+    #[arg(long, value_name = "SECS", default_value_t = 1.0, value_parser = parse_number)]
+    pub retry_delay: f64,
+
+    /// Factor the wait grows by after each failed attempt
+    #[arg(long, value_name = "FACTOR", default_value_t = 2.0, value_parser = parse_number)]
+    pub retry_multiplier: f64,
+
+    /// Longest wait between attempts in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 30.0, value_parser = parse_number)]
+    pub retry_max_delay: f64,
+
+    /// Part of each wait that is random, from 0 to 1, so threads do not retry together
+    #[arg(long, value_name = "RATIO", default_value_t = 0.5, value_parser = parse_ratio)]
+    pub retry_jitter: f64,
+
+    /// Attempts to connect again when connecting failed or the connection was lost
+    #[arg(long, value_name = "TIMES", default_value_t = 5)]
+    pub reconnect: u32,
+
+    /// Seconds to wait for connecting, TLS and SSH handshakes included, 0 waits forever
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for the reply of a command, 0 waits forever
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    pub command_timeout: u64,
+
+    /// Seconds without data flowing before a transfer is stalled, 0 waits forever
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    pub idle_timeout: u64,
+}
+
+/// Options of transfer output, shared by `upload`, `sync` and `download`.
+#[derive(ClapArgs, Debug)]
+pub struct OutputArgs {
+    /// Output format, `json` prints one event per line and a final summary
+    #[arg(long = "output", value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Write the run result as JSON into the file, including failed files
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+/// Bandwidth limits, shared by `upload`, `sync` and `download`.
+#[derive(ClapArgs, Debug)]
+pub struct LimitArgs {
+    /// Maximum rate of all connections together in bytes per second, like 500K or 5M
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    pub limit_rate: Option<u64>,
+
+    /// Maximum rate of each connection in bytes per second
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    pub limit_per_connection: Option<u64>,
+
+    /// Rate of all connections during a daily window in local time, like 09:00-18:00=1M
+    ///
+    /// Overrides `--limit-rate` inside the window, 0 is unlimited. Can be repeated, the
+    /// first matching window is used.
+    #[arg(long, value_name = "WINDOW", value_parser = parse_window)]
+    pub limit_schedule: Vec<RateWindow>,
+}
+
+/// Files left by a run for the next one, shared by `upload`, `sync` and `download`.
+#[derive(ClapArgs, Debug)]
+pub struct JournalArgs {
+    /// Where pending and failed files are written when interrupted by Ctrl-C
+    #[arg(long, default_value = "cymo-journal.json")]
+    pub journal: PathBuf,
+
+    /// Do not write the journal when interrupted
+    #[arg(long, conflicts_with = "journal")]
+    pub no_journal: bool,
+
+    /// Only transfer the pending and failed files of an interrupted run
+    #[arg(long, value_name = "JOURNAL", conflicts_with = "files_from")]
+    pub resume_journal: Option<PathBuf>,
+
+    /// Where failed files are written, one per line, for `--files-from`
+    ///
+    /// Removed when no file failed, so it never lists files of an earlier run.
+    #[arg(long, value_name = "FILE", default_value = "cymo-failed.txt")]
+    pub failed_list: PathBuf,
+
+    /// Do not write or remove the failed list
+    #[arg(long, conflicts_with = "failed_list")]
+    pub no_failed_list: bool,
+
+    /// Only transfer files listed one per line, `-` reads stdin
+    ///
+    /// Paths are relative to `--local-path`, or `--remote-path` for download, or start
+    /// with it like in `--failed-list`.
+    #[arg(long, value_name = "FILE")]
+    pub files_from: Option<PathBuf>,
+}
+
+/// Parse a window like `09:00-18:00=1M`.
+pub fn parse_window(value: &str) -> Result<RateWindow, String> {
+    let invalid = || format!("invalid window {}, expected like 09:00-18:00=1M", value);
+    let (times, rate) = value.split_once('=').ok_or_else(invalid)?;
+    let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+    let time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+    Ok(RateWindow {
+        start: time(start)?,
+        end: time(end)?,
+        rate: parse_size(rate)?,
+    })
+}
+
+impl From<Connection> for options::Connection {
+    fn from(connection: Connection) -> Self {
+        let Connection {
+            server,
+            profile: _,
+            port,
+            protocol,
+            username,
+            password,
+            password_file: _,
+            identity,
+            tls,
+            ca_file,
+            insecure,
+            thread,
+            retry,
+            retry_delay,
+            retry_multiplier,
+            retry_max_delay,
+            retry_jitter,
+            reconnect,
+            connect_timeout,
+            command_timeout,
+            idle_timeout,
+        } = connection;
+        options::Connection {
+            server,
+            port,
+            protocol: protocol.map(Into::into),
+            username,
+            password,
+            identity,
+            tls: tls.into(),
+            ca_file,
+            insecure,
+            thread,
+            retry,
+            retry_delay,
+            retry_multiplier,
+            retry_max_delay,
+            retry_jitter,
+            reconnect,
+            connect_timeout,
+            command_timeout,
+            idle_timeout,
+        }
+    }
+}
+
+/// Arguments of file transfer, `upload`, `sync` and `download`.
+#[derive(ClapArgs, Debug)]
+pub struct Args {
+    /// The remote path on the FTP server where files will be uploaded.
+    #[arg(short, long)]
+    pub remote_path: String,
+
+    /// The local path to the directory or file that will be uploaded to the FTP server.
+    #[arg(short, long)]
+    pub local_path: String,
+
+    #[command(flatten)]
+    pub connection: Connection,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub limit: LimitArgs,
+
+    #[command(flatten)]
+    pub journal: JournalArgs,
+
+    /// Continue partially uploaded files from the size already on server
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Upload to a temporary name in the same directory, rename to the final name when finished
+    #[arg(long)]
+    pub atomic: bool,
+
+    /// Compare hash or size of each uploaded file with the local file, mismatches are retried
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Upload into a new release beside the remote path, swap it into place when all files succeeded
+    ///
+    /// Releases are kept in `<remote-path>.releases`, `cymo rollback` puts the previous one back.
+    #[arg(long, conflicts_with = "delete")]
+    pub release: bool,
+
+    /// Previous releases kept for `cymo rollback`
+    #[arg(long, default_value_t = 5)]
+    pub keep_releases: usize,
+
+    /// Upload large files in this many parts at the same time, each over its own connection
+    ///
+    /// Only files of `--segment-threshold` or larger are split. FTP servers need `REST STREAM`, parts are written at their offset of the same file.
+    #[arg(long)]
+    pub segments: Option<usize>,
+
+    /// Smallest file uploaded in `--segments`, like 512M or 1G
+    #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+    pub segment_threshold: u64,
+
+    /// Skip files already up to date on server
+    #[arg(long, value_enum)]
+    pub skip_existing: Option<SkipPolicy>,
+
+    /// Delete remote files and directories not exist in local path after upload
+    #[arg(long)]
+    pub delete: bool,
+
+    /// Only print what `--delete` would remove
+    #[arg(long, requires = "delete")]
+    pub dry_run: bool,
+
+    /// Maximum remote entries `--delete` removes without `--force`
+    #[arg(long, requires = "delete", default_value_t = 100)]
+    pub max_delete: usize,
+
+    /// Delete without confirmation and `--max-delete` limit
+    #[arg(long, requires = "delete")]
+    pub force: bool,
+
+    /// Only upload files matching the glob, gitignore syntax, can be repeated
+    #[arg(long)]
+    pub include: Vec<String>,
+
+    /// Do not upload files or directories matching the glob, gitignore syntax, can be repeated
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// Upload hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+}
+
+impl From<Args> for Options {
+    fn from(args: Args) -> Self {
+        let Args {
+            remote_path,
+            local_path,
+            connection,
+            output,
+            limit,
+            journal,
+            resume,
+            atomic,
+            verify,
+            release,
+            keep_releases,
+            segments,
+            segment_threshold,
+            skip_existing,
+            delete,
+            dry_run,
+            max_delete,
+            force,
+            include,
+            exclude,
+            hidden,
+        } = args;
+        let transfer = DownloadArgs {
+            remote_path,
+            local_path,
+            connection,
+            output,
+            limit,
+            journal,
+            hidden,
+        };
+        Options {
+            resume,
+            atomic,
+            verify,
+            release,
+            keep_releases,
+            segments,
+            segment_threshold,
+            skip_existing: skip_existing.map(Into::into),
+            delete,
+            dry_run,
+            max_delete,
+            force,
+            include,
+            exclude,
+            download: false,
+            ..transfer.into()
+        }
+    }
+}
+
+/// Parse a size like `1024`, `512K`, `100MB` or `1GiB`, units are 1024 based.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let upper = value.trim().to_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, unit) = match number.char_indices().last() {
+        Some((index, 'K')) => (&number[..index], 1 << 10),
+        Some((index, 'M')) => (&number[..index], 1 << 20),
+        Some((index, 'G')) => (&number[..index], 1 << 30),
+        Some((index, 'T')) => (&number[..index], 1 << 40),
+        _ => (number, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .ok_or(format!(
+            "invalid size {}, expected like 512K, 100M or 1G",
+            value
+        ))
+}
+
+/// Parse a non-negative number like `1.5`.
+pub fn parse_number(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .ok_or(format!("invalid number {}, expected like 0.5 or 2", value))
+}
+
+/// Parse a number from 0 to 1.
+pub fn parse_ratio(value: &str) -> Result<f64, String> {
+    parse_number(value)
+        .ok()
+        .filter(|number| *number <= 1.0)
+        .ok_or(format!("invalid ratio {}, expected from 0 to 1", value))
+}
+
+/// Arguments of `download`, the options `upload` has too.
+#[derive(ClapArgs, Debug)]
+pub struct DownloadArgs {
+    /// The remote directory or file on the FTP server to be downloaded.
+    #[arg(short, long)]
+    pub remote_path: String,
+
+    /// The local directory where files will be downloaded into.
+    #[arg(short, long)]
+    pub local_path: String,
+
+    #[command(flatten)]
+    pub connection: Connection,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub limit: LimitArgs,
+
+    #[command(flatten)]
+    pub journal: JournalArgs,
+
+    /// Download hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+}
+
+/// Options of `download`, also the common part of `upload` and `sync`.
+impl From<DownloadArgs> for Options {
+    fn from(args: DownloadArgs) -> Self {
+        let DownloadArgs {
+            remote_path,
+            local_path,
+            connection,
+            output,
+            limit,
+            journal,
+            hidden,
+        } = args;
+        Options {
+            remote_path,
+            local_path,
+            connection: connection.into(),
+            report: output.report,
+            limit_rate: limit.limit_rate,
+            limit_per_connection: limit.limit_per_connection,
+            limit_schedule: limit.limit_schedule,
+            journal: (!journal.no_journal).then_some(journal.journal),
+            resume_journal: journal.resume_journal,
+            failed_list: (!journal.no_failed_list).then_some(journal.failed_list),
+            files_from: journal.files_from,
+            hidden,
+            download: true,
+            ..Options::default()
+        }
+    }
+}
+
+/// Arguments of `ls`.
+#[derive(ClapArgs, Debug)]
+pub struct LsArgs {
+    #[command(flatten)]
+    pub connection: Connection,
+
+    /// Remote directory to list
+    #[arg(default_value = ".")]
+    pub path: String,
+}
+
+/// Arguments of `rm`.
+#[derive(ClapArgs, Debug)]
+pub struct RmArgs {
+    #[command(flatten)]
+    pub connection: Connection,
+
+    /// Remote files or directories to remove
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+
+    /// Remove directories and their contents recursively
+    #[arg(short, long)]
+    pub recursive: bool,
+}
+
+/// Arguments of `mkdir`.
+#[derive(ClapArgs, Debug)]
+pub struct MkdirArgs {
+    #[command(flatten)]
+    pub connection: Connection,
+
+    /// Remote directories to create
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+
+    /// Create parent directories as needed, no error if existing
+    #[arg(long)]
+    pub parents: bool,
+}
+
+/// Arguments of `rollback`.
+#[derive(ClapArgs, Debug)]
+pub struct RollbackArgs {
+    #[command(flatten)]
+    pub connection: Connection,
+
+    /// The live remote path deployed with `upload --release`
+    #[arg(short, long)]
+    pub remote_path: String,
+
+    /// Release to put in place, like 20240110120000 [default: the one before the live release]
+    #[arg(long)]
+    pub to: Option<String>,
+}
+
+/// FTPS mode
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Plain FTP
+    None,
+    /// Upgrade with `AUTH TLS` after connected
+    Explicit,
+    /// TLS from the first byte, usually on port 990
+    Implicit,
+}
+
+impl From<TlsMode> for options::TlsMode {
+    fn from(value: TlsMode) -> Self {
+        match value {
+            TlsMode::None => options::TlsMode::None,
+            TlsMode::Explicit => options::TlsMode::Explicit,
+            TlsMode::Implicit => options::TlsMode::Implicit,
+        }
+    }
+}
+
+/// Protocol used to connect to the server
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// FTP, or FTPS with `--tls`
+    Ftp,
+    /// SFTP over SSH
+    Sftp,
+}
+
+impl From<Protocol> for options::Protocol {
+    fn from(value: Protocol) -> Self {
+        match value {
+            Protocol::Ftp => options::Protocol::Ftp,
+            Protocol::Sftp => options::Protocol::Sftp,
+        }
+    }
+}
+
+/// How to decide a remote file is up to date
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipPolicy {
+    /// Same size
+    Size,
+    /// Same size, and remote file is not older than local
+    Mtime,
+    /// Same size and hash, fall back to mtime when server has no hash command
+    Checksum,
+}
+
+impl From<SkipPolicy> for options::SkipPolicy {
+    fn from(value: SkipPolicy) -> Self {
+        match value {
+            SkipPolicy::Size => options::SkipPolicy::Size,
+            SkipPolicy::Mtime => options::SkipPolicy::Mtime,
+            SkipPolicy::Checksum => options::SkipPolicy::Checksum,
+        }
+    }
+}
+
+/// Format of transfer output
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Progress bars on a terminal, plain lines otherwise
+    Text,
+    /// Newline-delimited JSON events
+    Json,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Options of `upload` with the given flags.
+    fn upload(args: &[&str]) -> Options {
+        let args = [
+            "cymo",
+            "upload",
+            "-s",
+            "example.com",
+            "-r",
+            "/www",
+            "-l",
+            "dist",
+        ]
+        .iter()
+        .chain(args);
+        match Cli::try_parse_from(args).unwrap().command {
+            Command::Upload(args) => args.into(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let options = upload(&[]);
+        let default = Options::default();
+        assert_eq!(options.keep_releases, default.keep_releases);
+        assert_eq!(options.segment_threshold, default.segment_threshold);
+        assert_eq!(options.max_delete, default.max_delete);
+        let (connection, default) = (options.connection, default.connection);
+        assert_eq!(connection.tls, default.tls);
+        assert_eq!(connection.retry_delay, default.retry_delay);
+        assert_eq!(connection.retry_multiplier, default.retry_multiplier);
+        assert_eq!(connection.retry_max_delay, default.retry_max_delay);
+        assert_eq!(connection.retry_jitter, default.retry_jitter);
+        assert_eq!(connection.reconnect, default.reconnect);
+        assert_eq!(connection.connect_timeout, default.connect_timeout);
+        assert_eq!(connection.command_timeout, default.command_timeout);
+        assert_eq!(connection.idle_timeout, default.idle_timeout);
+    }
+
+    #[test]
+    fn journal() {
+        let options = upload(&[]);
+        assert_eq!(options.journal, Some(PathBuf::from("cymo-journal.json")));
+        assert_eq!(options.failed_list, Some(PathBuf::from("cymo-failed.txt")));
+        let options = upload(&["--no-journal", "--no-failed-list"]);
+        assert_eq!(options.journal, None);
+        assert_eq!(options.failed_list, None);
+    }
+
+    #[test]
+    fn download_hidden() {
+        let args = [
+            "cymo",
+            "download",
+            "-s",
+            "example.com",
+            "-r",
+            "/www",
+            "-l",
+            "dist",
+            "--hidden",
+        ];
+        match Cli::try_parse_from(args).unwrap().command {
+            Command::Download(args) => assert!(Options::from(args).hidden),
+            _ => unreachable!(),
+        }
+        assert!(upload(&["--hidden"]).hidden);
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size("100mb"), Ok(100 << 20));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+        assert_eq!(parse_size(" 2 T "), Ok(2 << 40));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("-1K").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn window() {
+        let window = parse_window("09:00-18:30=1M").unwrap();
+        assert_eq!(window.start, NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+        assert_eq!(window.end, NaiveTime::from_hms_opt(18, 30, 0).unwrap());
+        assert_eq!(window.rate, 1 << 20);
+        assert_eq!(parse_window("22:00-06:00=0").unwrap().rate, 0);
+        assert!(parse_window("09:00-18:00").is_err());
+        assert!(parse_window("09:00=1M").is_err());
+        assert!(parse_window("9-18=1M").is_err());
+        assert!(parse_window("09:00-25:00=1M").is_err());
+    }
 }
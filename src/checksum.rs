@@ -0,0 +1,247 @@
+use crate::transport::Transport;
+
+use anyhow::{anyhow, Result};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use suppaftp::Status;
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// Hash algorithms supported by both cymo and the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha1,
+    Md5,
+    Crc32,
+}
+
+impl Algorithm {
+    /// Name used by `FEAT` and `OPTS HASH`.
+    fn hash_name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha1 => "SHA-1",
+            Algorithm::Md5 => "MD5",
+            Algorithm::Crc32 => "CRC32",
+        }
+    }
+
+    /// Legacy command for the algorithm.
+    fn x_command(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "XSHA256",
+            Algorithm::Sha1 => "XSHA1",
+            Algorithm::Md5 => "XMD5",
+            Algorithm::Crc32 => "XCRC",
+        }
+    }
+}
+
+/// How to ask server for a file hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    /// Use `HASH` from draft-bryan-ftpext-hash, otherwise the `X*` command.
+    hash_command: bool,
+}
+
+impl Checksum {
+    /// Select the algorithm of `HASH` on another connection, `OPTS HASH` is kept per
+    /// connection.
+    pub async fn select(&self, ftp_stream: &mut dyn Transport) -> Result<()> {
+        if let (true, Some(ftp_stream)) = (self.hash_command, ftp_stream.as_ftp()) {
+            ftp_stream
+                .opts("HASH", Some(self.algorithm.hash_name()))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the strongest hash command advertised by `FEAT`.
+///
+/// `HASH` is preferred, its algorithm is selected by `OPTS HASH`, which is kept
+/// for the whole connection. Returns `None` when the server has no hash command,
+/// SFTP never has one.
+pub async fn detect(ftp_stream: &mut dyn Transport) -> Option<Checksum> {
+    let ftp_stream = ftp_stream.as_ftp()?;
+    let algorithms = [
+        Algorithm::Sha256,
+        Algorithm::Sha1,
+        Algorithm::Md5,
+        Algorithm::Crc32,
+    ];
+    let features = ftp_stream.feat().await.ok()?;
+    let feature = |name: &str| {
+        features
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone().unwrap_or_default())
+    };
+
+    // Like `HASH SHA-256*;SHA-1;MD5;CRC32`, star is current selected.
+    if let Some(names) = feature("HASH") {
+        for algorithm in algorithms {
+            let supported = names.split(';').any(|name| {
+                name.trim_end_matches('*')
+                    .eq_ignore_ascii_case(algorithm.hash_name())
+            });
+            if supported
+                && ftp_stream
+                    .opts("HASH", Some(algorithm.hash_name()))
+                    .await
+                    .is_ok()
+            {
+                return Some(Checksum {
+                    algorithm,
+                    hash_command: true,
+                });
+            }
+        }
+    }
+    algorithms
+        .into_iter()
+        .find(|algorithm| feature(algorithm.x_command()).is_some())
+        .map(|algorithm| Checksum {
+            algorithm,
+            hash_command: false,
+        })
+}
+
+/// Ask server for the hash of a remote file, in lower case hex.
+pub async fn remote_digest(
+    ftp_stream: &mut dyn Transport,
+    checksum: Checksum,
+    path: &str,
+) -> Result<String> {
+    let ftp_stream = ftp_stream
+        .as_ftp()
+        .ok_or(anyhow!("Hash command needs FTP"))?;
+    let command = if checksum.hash_command {
+        format!("HASH {}", path)
+    } else {
+        format!("{} {}", checksum.algorithm.x_command(), path)
+    };
+    // XMD5 replies 251 on some servers, which is unknown to suppaftp.
+    let response = ftp_stream
+        .custom_command(
+            command,
+            &[Status::File, Status::RequestedFileActionOk, Status::Unknown],
+        )
+        .await?;
+    let reply = response.as_string()?;
+    // `213 SHA-256 0-1024 <hash> <file>` for HASH, `250 <hash>` for others.
+    let mut words = reply.split_whitespace().skip(1);
+    let digest = if checksum.hash_command {
+        words.nth(2)
+    } else {
+        words.next()
+    }
+    .ok_or(anyhow!("Bad hash reply {}", reply))?;
+    normalize(checksum.algorithm, digest)
+}
+
+/// Compare an uploaded file with the local file.
+///
+/// The hash from the server is compared when it has a hash command, `checksum` is
+/// detected once for the run, otherwise only the size. Files sent in ASCII mode to a
+/// server converting line endings do not match.
+///
+/// ## Return
+///
+/// What was compared, like `SHA-256` or `size`.
+pub async fn verify(
+    ftp_stream: &mut dyn Transport,
+    checksum: Option<Checksum>,
+    local: &Path,
+    remote: &str,
+) -> Result<&'static str> {
+    let Some(checksum) = checksum else {
+        let local_size = local.metadata()?.len();
+        let remote_size = ftp_stream.size(remote).await?;
+        if local_size != remote_size {
+            return Err(anyhow!(
+                "Verify {} failed, size {} on server, {} in local",
+                remote,
+                remote_size,
+                local_size
+            ));
+        }
+        return Ok("size");
+    };
+    let remote_digest = remote_digest(ftp_stream, checksum, remote).await?;
+    let local_digest = local_digest(local, checksum.algorithm).await?;
+    if remote_digest != local_digest {
+        return Err(anyhow!(
+            "Verify {} failed, {} {} on server, {} in local",
+            remote,
+            checksum.algorithm.hash_name(),
+            remote_digest,
+            local_digest
+        ));
+    }
+    Ok(checksum.algorithm.hash_name())
+}
+
+/// Hash a local file, in lower case hex.
+pub async fn local_digest(path: &Path, algorithm: Algorithm) -> Result<String> {
+    let mut local = File::open(path).await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut hasher = Hasher::new(algorithm);
+    loop {
+        let len = local.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// CRC may be upper case or not zero padded.
+fn normalize(algorithm: Algorithm, digest: &str) -> Result<String> {
+    match algorithm {
+        Algorithm::Crc32 => u32::from_str_radix(digest, 16)
+            .map(|crc| format!("{:08x}", crc))
+            .map_err(|err| anyhow!("Bad CRC {} {}", digest, err)),
+        _ => Ok(digest.to_lowercase()),
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha1(Sha1),
+    Md5(Md5),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Crc32(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha1(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Md5(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        }
+    }
+}
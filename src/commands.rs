@@ -0,0 +1,196 @@
+use crate::{
+    eudora::login,
+    options::Connection,
+    remote::{list_tree, RemoteEntry},
+    report::Events,
+    transport::{connect, Transport},
+};
+
+use anyhow::{anyhow, Result};
+use futures::Future;
+use std::path::{Path, PathBuf};
+use tokio::runtime;
+
+/// Connect and login, then run a single remote command.
+pub fn run_remote<F, Fut>(connection: &Connection, events: &Events, task: F) -> Result<()>
+where
+    F: FnOnce(Box<dyn Transport>) -> Fut,
+    Fut: Future<Output = Result<Box<dyn Transport>>>,
+{
+    let rt = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("create tokio runtime failed");
+    rt.block_on(remote_task(connection, events, task))
+}
+
+/// Same as `run_remote` on the runtime already running.
+pub async fn remote_task<F, Fut>(connection: &Connection, events: &Events, task: F) -> Result<()>
+where
+    F: FnOnce(Box<dyn Transport>) -> Fut,
+    Fut: Future<Output = Result<Box<dyn Transport>>>,
+{
+    let mut ftp_stream = connect(connection).await?;
+    login(ftp_stream.as_mut(), connection, 0, events).await?;
+    let mut ftp_stream = task(ftp_stream).await?;
+    ftp_stream.quit().await?;
+    Ok(())
+}
+
+/// List a remote directory, entries are sorted by name.
+pub fn ls(connection: &Connection, path: &str, events: &Events) -> Result<Vec<RemoteEntry>> {
+    let mut entries = vec![];
+    run_remote(connection, events, |mut ftp_stream| async {
+        entries = ftp_stream.list_dir(path).await?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ftp_stream)
+    })?;
+    Ok(entries)
+}
+
+/// Remove remote files, or directories with `recursive`.
+///
+/// Without `recursive` only empty directories can be removed.
+pub fn rm(
+    connection: &Connection,
+    paths: &[String],
+    recursive: bool,
+    events: &Events,
+) -> Result<()> {
+    run_remote(connection, events, |mut ftp_stream| async move {
+        for path in paths {
+            if ftp_stream.rm(path).await.is_ok() {
+                events.info(format!("Remove {} success", path));
+                continue;
+            }
+            if recursive {
+                remove_tree(ftp_stream.as_mut(), path, events).await?;
+            } else {
+                ftp_stream
+                    .rmdir(path)
+                    .await
+                    .map_err(|err| anyhow!("Remove {} failed {}", path, err))?;
+                events.info(format!("Remove {} success", path));
+            }
+        }
+        Ok(ftp_stream)
+    })
+}
+
+/// Create remote directories, and their parents with `parents`.
+pub fn mkdir(
+    connection: &Connection,
+    paths: &[String],
+    parents: bool,
+    events: &Events,
+) -> Result<()> {
+    run_remote(connection, events, |mut ftp_stream| async move {
+        for path in paths {
+            if !parents {
+                ftp_stream
+                    .mkdir(path)
+                    .await
+                    .map_err(|err| anyhow!("Create {} failed {}", path, err))?;
+                events.info(format!("Create directory {} success", path));
+                continue;
+            }
+            mkdir_parents(ftp_stream.as_mut(), path, events).await?;
+        }
+        Ok(ftp_stream)
+    })
+}
+
+/// Remove a remote directory and everything in it.
+pub async fn remove_tree(
+    ftp_stream: &mut dyn Transport,
+    path: &str,
+    events: &Events,
+) -> Result<()> {
+    let (files, dirs) = list_tree(ftp_stream, Path::new(path), true).await?;
+    for file in files {
+        ftp_stream.rm(&file.to_string_lossy()).await?;
+        events.info(format!("Remove {:?} success", file));
+    }
+    // Children are always after their parent, remove from the end.
+    for dir in dirs.iter().rev() {
+        ftp_stream.rmdir(&dir.to_string_lossy()).await?;
+        events.info(format!("Remove {:?} success", dir));
+    }
+    ftp_stream
+        .rmdir(path)
+        .await
+        .map_err(|err| anyhow!("Remove {} failed {}", path, err))?;
+    events.info(format!("Remove {} success", path));
+    Ok(())
+}
+
+/// Create a remote directory and its missing parents, no error if existing.
+pub async fn mkdir_parents(
+    ftp_stream: &mut dyn Transport,
+    path: &str,
+    events: &Events,
+) -> Result<()> {
+    // Relative paths are resolved from here, go back after every probe.
+    let home = ftp_stream.pwd().await?;
+    let mut current = PathBuf::new();
+    for component in Path::new(path).components() {
+        current.push(component);
+        let dir = current.to_string_lossy();
+        // Existing directory can be changed into.
+        if ftp_stream.cwd(&dir).await.is_ok() {
+            ftp_stream.cwd(&home).await?;
+            continue;
+        }
+        ftp_stream
+            .mkdir(&dir)
+            .await
+            .map_err(|err| anyhow!("Create {} failed {}", dir, err))?;
+        events.info(format!("Create directory {} success", dir));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryTransport;
+
+    #[tokio::test]
+    async fn mkdir_parents_relative_to_existing() {
+        let mut ftp_stream = MemoryTransport::new(&[], &["/a"]);
+        mkdir_parents(&mut ftp_stream, "a/b/c", &Events::default())
+            .await
+            .unwrap();
+        mkdir_parents(&mut ftp_stream, "a/d", &Events::default())
+            .await
+            .unwrap();
+        let dirs = ftp_stream.entries.keys().cloned().collect::<Vec<_>>();
+        let expected = ["/", "/a", "/a/b", "/a/b/c", "/a/d"].map(PathBuf::from);
+        assert_eq!(dirs, expected);
+        assert_eq!(ftp_stream.pwd().await.unwrap(), "/");
+    }
+
+    #[tokio::test]
+    async fn remove_tree_with_hidden_entries() {
+        let mut ftp_stream = MemoryTransport::new(
+            &["/www/.htaccess", "/www/.git/HEAD", "/www/a/.b.cymo-partial"],
+            &["/keep"],
+        );
+        remove_tree(&mut ftp_stream, "/www", &Events::default())
+            .await
+            .unwrap();
+        let dirs = ftp_stream.entries.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(dirs, ["/", "/keep"].map(PathBuf::from));
+    }
+
+    #[tokio::test]
+    async fn mkdir_parents_absolute() {
+        let mut ftp_stream = MemoryTransport::new(&[], &["/www"]);
+        ftp_stream.cwd("/www").await.unwrap();
+        mkdir_parents(&mut ftp_stream, "/www/a/b", &Events::default())
+            .await
+            .unwrap();
+        assert!(ftp_stream.entries.contains_key(Path::new("/www/a/b")));
+        assert_eq!(ftp_stream.pwd().await.unwrap(), "/www");
+    }
+}
@@ -0,0 +1,465 @@
+use crate::args::{
+    parse_number, parse_ratio, parse_size, parse_window, Cli, OutputFormat, Protocol, SkipPolicy,
+    TlsMode,
+};
+
+use anyhow::{anyhow, Result};
+use clap::{
+    builder::Resettable, Command as ClapCommand, CommandFactory, FromArgMatches, ValueEnum,
+};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Config file in the working directory, its profiles take precedence.
+const LOCAL_CONFIG: &str = "cymo.toml";
+
+/// Config file of the user.
+fn user_config() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/cymo/config.toml"))
+}
+
+/// Content of a config file.
+///
+/// ```toml
+/// [profiles.staging]
+/// server = "sftp://staging.example.com"
+/// remote-path = "/var/www"
+/// local-path = "dist"
+/// thread = 4
+/// exclude = ["*.map"]
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Named set of options, keys are the long flags.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Profile {
+    server: Option<String>,
+    port: Option<u32>,
+    protocol: Option<Protocol>,
+    username: Option<String>,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    identity: Option<PathBuf>,
+    tls: Option<TlsMode>,
+    ca_file: Option<PathBuf>,
+    insecure: Option<bool>,
+    thread: Option<usize>,
+    retry: Option<u32>,
+    #[serde(default, deserialize_with = "number")]
+    retry_delay: Option<f64>,
+    #[serde(default, deserialize_with = "number")]
+    retry_multiplier: Option<f64>,
+    #[serde(default, deserialize_with = "number")]
+    retry_max_delay: Option<f64>,
+    #[serde(default, deserialize_with = "ratio")]
+    retry_jitter: Option<f64>,
+    reconnect: Option<u32>,
+    connect_timeout: Option<u64>,
+    command_timeout: Option<u64>,
+    idle_timeout: Option<u64>,
+    remote_path: Option<String>,
+    local_path: Option<String>,
+    resume: Option<bool>,
+    atomic: Option<bool>,
+    verify: Option<bool>,
+    release: Option<bool>,
+    keep_releases: Option<usize>,
+    segments: Option<usize>,
+    #[serde(default, deserialize_with = "size")]
+    segment_threshold: Option<u64>,
+    skip_existing: Option<SkipPolicy>,
+    delete: Option<bool>,
+    dry_run: Option<bool>,
+    max_delete: Option<usize>,
+    force: Option<bool>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    hidden: Option<bool>,
+    output: Option<OutputFormat>,
+    report: Option<PathBuf>,
+    #[serde(default, deserialize_with = "size")]
+    limit_rate: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    limit_per_connection: Option<u64>,
+    #[serde(default, deserialize_with = "windows")]
+    limit_schedule: Option<Vec<String>>,
+    journal: Option<PathBuf>,
+    no_journal: Option<bool>,
+    resume_journal: Option<PathBuf>,
+    failed_list: Option<PathBuf>,
+    no_failed_list: Option<bool>,
+    files_from: Option<PathBuf>,
+}
+
+/// Parse command line, values of `--profile` are used for flags not given.
+///
+/// Profile values become the default values of flags, so explicit flags always
+/// win and required flags like `--server` can come from the profile.
+pub fn parse() -> Result<Cli> {
+    // `sync` always deletes, its delete options do not need `--delete`.
+    let mut command = Cli::command().mut_subcommand("sync", |sync| {
+        ["dry_run", "max_delete", "force"]
+            .into_iter()
+            .fold(sync, |sync, id| {
+                sync.mut_arg(id, |arg| arg.requires(Resettable::Reset))
+            })
+    });
+    let mut delete_keys = vec![];
+    if let Some(name) = profile_name(env::args_os()) {
+        let (path, profile) = load(&name)?;
+        delete_keys = profile
+            .delete_keys()
+            .into_iter()
+            .map(|key| format!("{} of profile {} in {:?}", key, name, path))
+            .collect();
+        let defaults = profile.defaults();
+        let subcommands = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect::<Vec<_>>();
+        for name in subcommands {
+            command = command.mut_subcommand(name, |subcommand| apply(subcommand, &defaults));
+        }
+    }
+    let matches = command.get_matches();
+    // Default values do not trigger `requires = "delete"` of `upload`.
+    if let (Some(("upload", upload)), Some(key)) = (matches.subcommand(), delete_keys.first()) {
+        if !upload.get_flag("delete") {
+            return Err(anyhow!("{} is only used with --delete", key));
+        }
+    }
+    Ok(Cli::from_arg_matches(&matches)?)
+}
+
+/// Find the value of `--profile` before clap parses, it changes the default values.
+fn profile_name(args: impl Iterator<Item = OsString>) -> Option<String> {
+    let mut args = args.skip(1).map(|arg| arg.to_string_lossy().to_string());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--profile" {
+            return args.next();
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// Find the profile in `./cymo.toml` first, then `~/.config/cymo/config.toml`.
+///
+/// Returns the file it was found in with it.
+fn load(name: &str) -> Result<(PathBuf, Profile)> {
+    let paths = [Some(PathBuf::from(LOCAL_CONFIG)), user_config()];
+    let mut searched = vec![];
+    for path in paths.into_iter().flatten() {
+        if let Some(mut config) = read(&path)? {
+            if let Some(profile) = config.profiles.remove(name) {
+                profile
+                    .check()
+                    .map_err(|err| anyhow!("Profile {} in {:?} is invalid, {}", name, path, err))?;
+                return Ok((path, profile));
+            }
+            searched.push(path.to_string_lossy().to_string());
+        }
+    }
+    if searched.is_empty() {
+        return Err(anyhow!(
+            "Profile {} not found, no {} or ~/.config/cymo/config.toml",
+            name,
+            LOCAL_CONFIG
+        ));
+    }
+    Err(anyhow!(
+        "Profile {} not found in {}",
+        name,
+        searched.join(", ")
+    ))
+}
+
+/// Read a config file, `None` when it does not exist.
+///
+/// Errors of invalid keys or values have the line and column.
+fn read(path: &Path) -> Result<Option<Config>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow!("Read config {:?} failed {}", path, err)),
+    };
+    toml::from_str(&content)
+        .map(Some)
+        .map_err(|err| anyhow!("Parse config {:?} failed, {}", path, err))
+}
+
+/// Set default values of flags the subcommand has, others are ignored.
+fn apply(mut subcommand: ClapCommand, defaults: &[(&'static str, Vec<String>)]) -> ClapCommand {
+    for (id, values) in defaults {
+        if subcommand.get_arguments().any(|arg| arg.get_id() == id) {
+            // Required args are not satisfied by default values, the profile fills them.
+            // Keep the password out of `--help`.
+            subcommand = subcommand.mut_arg(*id, |arg| {
+                arg.default_values(values.clone())
+                    .required(false)
+                    .hide_default_value(*id == "password")
+            });
+        }
+    }
+    subcommand
+}
+
+/// Name of a value enum used on the command line.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Size like `100M`, errors have the position in the config file.
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_size(&value).map(Some).map_err(de::Error::custom)
+}
+
+/// Non-negative number, errors have the position in the config file.
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    parse_number(&value.to_string())
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// Number from 0 to 1, errors have the position in the config file.
+fn ratio<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    parse_ratio(&value.to_string())
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// Windows like `09:00-18:00=1M`, errors have the position in the config file.
+fn windows<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    let values = Vec::<String>::deserialize(deserializer)?;
+    for value in &values {
+        parse_window(value).map_err(de::Error::custom)?;
+    }
+    Ok(Some(values))
+}
+
+fn path_value(path: PathBuf) -> String {
+    path.to_string_lossy().to_string()
+}
+
+impl Profile {
+    /// Check keys that can not be used together, clap does not check default values.
+    fn check(&self) -> Result<(), String> {
+        let conflicts = [
+            (
+                "release",
+                self.release == Some(true),
+                "delete",
+                self.delete == Some(true),
+            ),
+            (
+                "journal",
+                self.journal.is_some(),
+                "no-journal",
+                self.no_journal == Some(true),
+            ),
+            (
+                "resume-journal",
+                self.resume_journal.is_some(),
+                "files-from",
+                self.files_from.is_some(),
+            ),
+            (
+                "failed-list",
+                self.failed_list.is_some(),
+                "no-failed-list",
+                self.no_failed_list == Some(true),
+            ),
+        ];
+        match conflicts
+            .into_iter()
+            .find(|(_, set, _, other)| *set && *other)
+        {
+            Some((key, _, other, _)) => Err(format!("{} can not be used with {}", key, other)),
+            None => Ok(()),
+        }
+    }
+
+    /// Keys `upload` only takes with `delete`, `sync` always deletes.
+    fn delete_keys(&self) -> Vec<&'static str> {
+        if self.delete == Some(true) {
+            return vec![];
+        }
+        [
+            ("dry-run", self.dry_run == Some(true)),
+            ("max-delete", self.max_delete.is_some()),
+            ("force", self.force == Some(true)),
+        ]
+        .into_iter()
+        .filter_map(|(key, set)| set.then_some(key))
+        .collect()
+    }
+
+    /// Values as default values of clap args, by arg id.
+    fn defaults(self) -> Vec<(&'static str, Vec<String>)> {
+        let single = [
+            ("server", self.server),
+            ("port", self.port.map(|port| port.to_string())),
+            ("protocol", self.protocol.map(value_name)),
+            ("username", self.username),
+            ("password", self.password),
+            ("password_file", self.password_file.map(path_value)),
+            ("identity", self.identity.map(path_value)),
+            ("tls", self.tls.map(value_name)),
+            ("ca_file", self.ca_file.map(path_value)),
+            (
+                "insecure",
+                self.insecure.map(|insecure| insecure.to_string()),
+            ),
+            ("thread", self.thread.map(|thread| thread.to_string())),
+            ("retry", self.retry.map(|retry| retry.to_string())),
+            (
+                "retry_delay",
+                self.retry_delay.map(|seconds| seconds.to_string()),
+            ),
+            (
+                "retry_multiplier",
+                self.retry_multiplier.map(|factor| factor.to_string()),
+            ),
+            (
+                "retry_max_delay",
+                self.retry_max_delay.map(|seconds| seconds.to_string()),
+            ),
+            (
+                "retry_jitter",
+                self.retry_jitter.map(|ratio| ratio.to_string()),
+            ),
+            (
+                "reconnect",
+                self.reconnect.map(|reconnect| reconnect.to_string()),
+            ),
+            (
+                "connect_timeout",
+                self.connect_timeout.map(|seconds| seconds.to_string()),
+            ),
+            (
+                "command_timeout",
+                self.command_timeout.map(|seconds| seconds.to_string()),
+            ),
+            (
+                "idle_timeout",
+                self.idle_timeout.map(|seconds| seconds.to_string()),
+            ),
+            ("remote_path", self.remote_path),
+            ("local_path", self.local_path),
+            ("resume", self.resume.map(|resume| resume.to_string())),
+            ("atomic", self.atomic.map(|atomic| atomic.to_string())),
+            ("verify", self.verify.map(|verify| verify.to_string())),
+            ("release", self.release.map(|release| release.to_string())),
+            (
+                "keep_releases",
+                self.keep_releases.map(|keep| keep.to_string()),
+            ),
+            (
+                "segments",
+                self.segments.map(|segments| segments.to_string()),
+            ),
+            (
+                "segment_threshold",
+                self.segment_threshold.map(|size| size.to_string()),
+            ),
+            ("skip_existing", self.skip_existing.map(value_name)),
+            ("delete", self.delete.map(|delete| delete.to_string())),
+            ("dry_run", self.dry_run.map(|dry_run| dry_run.to_string())),
+            ("max_delete", self.max_delete.map(|max| max.to_string())),
+            ("force", self.force.map(|force| force.to_string())),
+            ("hidden", self.hidden.map(|hidden| hidden.to_string())),
+            ("format", self.output.map(value_name)),
+            ("report", self.report.map(path_value)),
+            ("limit_rate", self.limit_rate.map(|rate| rate.to_string())),
+            (
+                "limit_per_connection",
+                self.limit_per_connection.map(|rate| rate.to_string()),
+            ),
+            ("journal", self.journal.map(path_value)),
+            ("no_journal", self.no_journal.map(|no| no.to_string())),
+            ("resume_journal", self.resume_journal.map(path_value)),
+            ("failed_list", self.failed_list.map(path_value)),
+            (
+                "no_failed_list",
+                self.no_failed_list.map(|no| no.to_string()),
+            ),
+            ("files_from", self.files_from.map(path_value)),
+        ];
+        let multiple = [
+            ("include", self.include),
+            ("exclude", self.exclude),
+            ("limit_schedule", self.limit_schedule),
+        ];
+        single
+            .into_iter()
+            .filter_map(|(id, value)| Some((id, vec![value?])))
+            .chain(
+                multiple
+                    .into_iter()
+                    .filter_map(|(id, values)| Some((id, values?))),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(content: &str) -> Result<Profile, toml::de::Error> {
+        let mut config: Config = toml::from_str(&format!("[profiles.a]\n{}", content))?;
+        Ok(config.profiles.remove("a").unwrap())
+    }
+
+    #[test]
+    fn invalid_values() {
+        let err = profile("retry-jitter = 2").unwrap_err().to_string();
+        assert!(
+            err.contains("line 2") && err.contains("invalid ratio 2"),
+            "{}",
+            err
+        );
+        assert!(profile("retry-delay = -1").is_err());
+        assert!(profile("limit-schedule = [\"09:00=1M\"]").is_err());
+        let valid = profile("retry-delay = 2\nretry-jitter = 0.25").unwrap();
+        assert_eq!(valid.retry_delay, Some(2.0));
+        assert_eq!(valid.retry_jitter, Some(0.25));
+    }
+
+    #[test]
+    fn conflicts() {
+        let release = profile("release = true\ndelete = true").unwrap();
+        assert_eq!(
+            release.check(),
+            Err("release can not be used with delete".to_string())
+        );
+        let dry_run = profile("dry-run = true\nmax-delete = 10").unwrap();
+        assert_eq!(dry_run.check(), Ok(()));
+        assert_eq!(dry_run.delete_keys(), ["dry-run", "max-delete"]);
+        let delete = profile("delete = true\nforce = true").unwrap();
+        assert!(delete.delete_keys().is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{
+    checksum::{self, Checksum},
+    interrupt::Stop,
+    options::Options,
+    report::{Event, Events},
+    throttle::Limiter,
+    transport::Transport,
+};
+
+use anyhow::Result;
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::OnceCell;
+
+/// Ask before `--delete` removes the given count of remote entries.
+pub type Confirm = Box<dyn Fn(usize) -> bool + Send + Sync>;
+
+/// State of one run, shared by all its threads.
+///
+/// Runs do not share anything, many of them can run in the same process.
+pub struct Context {
+    pub options: Options,
+    pub events: Events,
+    pub stop: Arc<Stop>,
+    /// Bucket of `--limit-rate`, shared by all connections.
+    pub limiter: Limiter,
+    /// Asked before `--delete`, entries are removed without asking when `None`.
+    pub confirm: Option<Confirm>,
+    /// Bytes actually sent or received, retried bytes included.
+    pub sent: AtomicU64,
+    /// Connections tried by all threads, reconnections included.
+    pub attempts: AtomicUsize,
+    /// Connections opened again after one was lost.
+    pub reconnects: AtomicUsize,
+    /// Hash command of the server, detected by the first connection needing it.
+    checksum: OnceCell<Option<Checksum>>,
+}
+
+impl Context {
+    pub fn new(
+        options: Options,
+        events: Events,
+        stop: Arc<Stop>,
+        confirm: Option<Confirm>,
+    ) -> Self {
+        Context {
+            options,
+            events,
+            stop,
+            limiter: Limiter::default(),
+            confirm,
+            sent: AtomicU64::new(0),
+            attempts: AtomicUsize::new(0),
+            reconnects: AtomicUsize::new(0),
+            checksum: OnceCell::new(),
+        }
+    }
+
+    /// `--local-path`, used to skip its folders.
+    pub fn local_root(&self) -> &Path {
+        Path::new(&self.options.local_path)
+    }
+
+    /// `--remote-path`, files are put under it.
+    pub fn remote_root(&self) -> &Path {
+        Path::new(&self.options.remote_path)
+    }
+
+    pub fn emit(&self, event: Event) {
+        self.events.emit(event);
+    }
+
+    pub fn info(&self, message: impl Display) {
+        self.events.info(message);
+    }
+
+    pub fn warn(&self, message: impl Display) {
+        self.events.warn(message);
+    }
+
+    /// Detect the hash command of the server once for the run, other connections only
+    /// select its algorithm.
+    pub async fn select_checksum(&self, ftp_stream: &mut dyn Transport) -> Result<()> {
+        let mut detected = false;
+        let checksum = self
+            .checksum
+            .get_or_init(|| {
+                detected = true;
+                checksum::detect(ftp_stream)
+            })
+            .await;
+        match checksum {
+            Some(checksum) if !detected => checksum.select(ftp_stream).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Hash command found by `select_checksum`, `None` when the server has none.
+    pub fn checksum(&self) -> Option<Checksum> {
+        self.checksum.get().copied().flatten()
+    }
+
+    /// Connection attempts and reconnections of all threads, for the summary.
+    pub fn connection_stats(&self) -> (usize, usize) {
+        (
+            self.attempts.load(Ordering::Relaxed),
+            self.reconnects.load(Ordering::Relaxed),
+        )
+    }
+}
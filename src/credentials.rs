@@ -0,0 +1,197 @@
+use crate::args::Connection;
+
+use cymo::options::{self, Protocol};
+
+use anyhow::{anyhow, Result};
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+
+/// Environment variable of the username.
+const USER_ENV: &str = "CYMO_USER";
+/// Environment variable of the password.
+const PASSWORD_ENV: &str = "CYMO_PASSWORD";
+
+/// Fill in missing username and password of the connection.
+///
+/// Each is looked up in order, the first found wins:
+///
+/// 1. `--username`, `--password`
+/// 2. `CYMO_USER`, `CYMO_PASSWORD`
+/// 3. `--password-file`
+/// 4. `~/.netrc`, or the file in `NETRC`, by the server hostname
+/// 5. A prompt without echo, only when stdin is a TTY
+///
+/// Without any username the server is used anonymously, a password without a
+/// username is an error. SFTP with `--identity` does not prompt, the password
+/// is only the passphrase of the key there.
+pub fn resolve(connection: &mut Connection) -> Result<()> {
+    // Host and protocol as the library sees them.
+    let server = options::Connection::from(connection.clone());
+    if connection.username.is_none() {
+        connection.username = env::var(USER_ENV).ok().filter(|user| !user.is_empty());
+    }
+    if connection.password.is_none() {
+        connection.password = env::var(PASSWORD_ENV).ok();
+    }
+    if let (None, Some(path)) = (&connection.password, &connection.password_file) {
+        let password = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Read password file {:?} failed {}", path, err))?;
+        connection.password = Some(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+    if connection.password.is_none() {
+        if let Some(entry) = netrc(server.host())? {
+            // Only take the password of the same user.
+            match &connection.username {
+                Some(username) if entry.login.as_ref() != Some(username) => {}
+                _ => {
+                    connection.username = connection.username.take().or(entry.login);
+                    connection.password = entry.password;
+                }
+            }
+        }
+    }
+
+    match (&connection.username, &connection.password) {
+        (None, Some(_)) => Err(anyhow!(
+            "Password is provided without username, set --username or {}",
+            USER_ENV
+        )),
+        (Some(username), None) => {
+            if server.protocol() == Protocol::Sftp && connection.identity.is_some() {
+                return Ok(());
+            }
+            if !io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "No password of {} found, set {}, --password-file or ~/.netrc",
+                    username,
+                    PASSWORD_ENV
+                ));
+            }
+            let password = rpassword::prompt_password(format!(
+                "Password of {}@{}: ",
+                username,
+                server.host()
+            ))?;
+            connection.password = Some(password);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Credentials of a `machine` or `default` entry in `.netrc`.
+#[derive(Debug, Default)]
+struct NetrcEntry {
+    /// `None` for the `default` entry.
+    machine: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+/// Find the entry of `host` in the netrc file, the `default` entry when no machine matches.
+///
+/// A missing file is not an error.
+fn netrc(host: &str) -> Result<Option<NetrcEntry>> {
+    let Some(path) = env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc")))
+    else {
+        return Ok(None);
+    };
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow!("Read netrc {:?} failed {}", path, err)),
+    };
+    let mut entries = parse_netrc(&content);
+    let index = entries
+        .iter()
+        .position(|entry| {
+            entry
+                .machine
+                .as_deref()
+                .is_some_and(|machine| machine.eq_ignore_ascii_case(host))
+        })
+        .or_else(|| entries.iter().position(|entry| entry.machine.is_none()));
+    Ok(index.map(|index| entries.swap_remove(index)))
+}
+
+/// Parse netrc tokens into entries, `macdef` bodies are skipped until an empty line.
+fn parse_netrc(content: &str) -> Vec<NetrcEntry> {
+    let mut entries: Vec<NetrcEntry> = vec![];
+    let mut in_macro = false;
+    for line in content.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => entries.push(NetrcEntry {
+                    machine: tokens.next().map(str::to_string),
+                    ..Default::default()
+                }),
+                "default" => entries.push(NetrcEntry::default()),
+                "login" | "password" => {
+                    let value = tokens.next().map(str::to_string);
+                    if let Some(entry) = entries.last_mut() {
+                        if token == "login" {
+                            entry.login = value;
+                        } else {
+                            entry.password = value;
+                        }
+                    }
+                }
+                "macdef" => {
+                    in_macro = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(entry: &NetrcEntry) -> (Option<&str>, Option<&str>, Option<&str>) {
+        (
+            entry.machine.as_deref(),
+            entry.login.as_deref(),
+            entry.password.as_deref(),
+        )
+    }
+
+    #[test]
+    fn netrc() {
+        let entries = parse_netrc(
+            "machine ftp.example.com login deploy password secret\n\
+             machine sftp.example.com\n  login admin\n  password p@ss\n\
+             macdef init\n  machine evil.example.com login x\n\n\
+             default login anonymous password guest\n",
+        );
+        let entries = entries.iter().map(fields).collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (Some("ftp.example.com"), Some("deploy"), Some("secret")),
+                (Some("sftp.example.com"), Some("admin"), Some("p@ss")),
+                (None, Some("anonymous"), Some("guest")),
+            ]
+        );
+    }
+
+    #[test]
+    fn netrc_without_machine() {
+        let entries = parse_netrc("login lost password lost\nmachine example.com");
+        let entries = entries.iter().map(fields).collect::<Vec<_>>();
+        assert_eq!(entries, [(Some("example.com"), None, None)]);
+    }
+}
@@ -0,0 +1,197 @@
+use cymo::Event;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, IsTerminal},
+    sync::Mutex,
+};
+
+/// Live terminal view of a transfer, drawn from the events of the run.
+///
+/// One bar per active worker shows the file name, bytes sent and rate, the overall bar
+/// shows bytes and files done with ETA. When stdout is not a TTY nothing is drawn and
+/// every message is printed line by line as before. With `--output json` nothing is
+/// drawn either, file events are printed as JSON instead.
+pub struct Dashboard {
+    json: bool,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// `None` when not drawn, before files are found and after all threads finished.
+    multi: Option<MultiProgress>,
+    total: ProgressBar,
+    /// Total bytes is not known before files start, like downloads.
+    growing: bool,
+    /// Bar of the file in progress on each thread.
+    bars: HashMap<usize, ProgressBar>,
+    files: usize,
+    done: usize,
+    failed: usize,
+}
+
+impl Dashboard {
+    pub fn new(json: bool) -> Self {
+        Dashboard {
+            json,
+            state: Mutex::new(State {
+                multi: None,
+                total: ProgressBar::hidden(),
+                growing: false,
+                bars: HashMap::new(),
+                files: 0,
+                done: 0,
+                failed: 0,
+            }),
+        }
+    }
+
+    pub fn handle(&self, event: Event) {
+        if self.json && is_printed(&event) {
+            match serde_json::to_string(&event) {
+                Ok(line) => println!("{}", line),
+                Err(err) => eprintln!("Serialize event failed {}", err),
+            }
+        }
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        match event {
+            // The dashboard shows the same, nothing is printed with `--output json` since
+            // stdout only has events.
+            Event::Info { message } => {
+                if state.multi.is_none() && !self.json {
+                    println!("{}", message);
+                }
+            }
+            Event::Warn { message } => state.warn(message),
+            Event::Started { files, bytes } => {
+                if io::stdout().is_terminal() && !self.json {
+                    state.start(files, bytes);
+                }
+            }
+            Event::FileStarted { thread, path, size } => {
+                if let Some(multi) = &state.multi {
+                    let bar = multi.insert_before(
+                        &state.total,
+                        ProgressBar::new(size)
+                            .with_style(
+                                ProgressStyle::with_template(
+                                    "Thread {prefix:>2} {wide_msg} {bytes}/{total_bytes} {binary_bytes_per_sec}",
+                                )
+                                .expect("progress template"),
+                            )
+                            .with_prefix(thread.to_string())
+                            .with_message(path.to_string_lossy().to_string()),
+                    );
+                    if let Some(previous) = state.bars.insert(thread, bar) {
+                        previous.finish_and_clear();
+                    }
+                }
+                if state.growing {
+                    state.total.inc_length(size);
+                }
+            }
+            Event::FileProgress { thread, bytes } => {
+                if let Some(bar) = state.bars.get(&thread) {
+                    bar.inc(bytes);
+                }
+                state.total.inc(bytes);
+            }
+            // Bytes of the failed attempt will be transferred again.
+            Event::FileRetried { thread, bytes } => {
+                state.total.inc_length(bytes);
+                state.clear(thread);
+            }
+            Event::FileFinished { thread, .. } => {
+                state.done += 1;
+                state.clear(thread);
+                state.update_message();
+            }
+            Event::FileFailed { .. } => {
+                state.failed += 1;
+                state.update_message();
+            }
+            Event::FileSkipped { size } => {
+                state.files = state.files.saturating_sub(1);
+                let length = state.total.length().unwrap_or(0).saturating_sub(size);
+                state.total.set_length(length);
+                state.update_message();
+            }
+            Event::Finished => {
+                if let Some(multi) = state.multi.take() {
+                    state.total.finish_and_clear();
+                    let _ = multi.clear();
+                }
+            }
+            Event::Connected { .. } | Event::Mkdir { .. } | Event::Summary(_) => {}
+        }
+    }
+
+    /// Print an error line above the dashboard, or to stderr when it is not drawn.
+    pub fn warn(&self, line: impl Display) {
+        if let Ok(state) = self.state.lock() {
+            state.warn(line);
+        }
+    }
+}
+
+impl State {
+    fn start(&mut self, files: usize, bytes: Option<u64>) {
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
+        self.total = multi.add(
+            ProgressBar::new(bytes.unwrap_or(0)).with_style(
+                ProgressStyle::with_template(
+                    "Total    [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta} {msg}",
+                )
+                .expect("progress template")
+                .progress_chars("=> "),
+            ),
+        );
+        self.multi = Some(multi);
+        self.growing = bytes.is_none();
+        self.files = files;
+        self.update_message();
+    }
+
+    fn warn(&self, line: impl Display) {
+        match &self.multi {
+            Some(multi) => {
+                let _ = multi.println(line.to_string());
+            }
+            None => eprintln!("{}", line),
+        }
+    }
+
+    /// Remove the bar of the file finished on the thread.
+    fn clear(&mut self, thread: usize) {
+        if let Some(bar) = self.bars.remove(&thread) {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn update_message(&self) {
+        let message = format!("{}/{} files", self.done, self.files);
+        if self.failed > 0 {
+            self.total
+                .set_message(format!("{}, {} failed", message, self.failed));
+        } else {
+            self.total.set_message(message);
+        }
+    }
+}
+
+/// Events printed with `--output json`, one JSON object per line.
+fn is_printed(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Connected { .. }
+            | Event::Mkdir { .. }
+            | Event::FileStarted { .. }
+            | Event::FileFinished { .. }
+            | Event::FileFailed { .. }
+            | Event::Summary(_)
+    )
+}
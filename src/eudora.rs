@@ -1,148 +1,180 @@
-use crate::args::Args;
-use crate::{ARG, PARAM_PATH, REMOTE_PATH};
+use crate::checksum;
+use crate::context::Context;
+use crate::options::{Connection, Options, SkipPolicy};
+use crate::reconnect::{connect_session, is_disconnected, reconnect_session};
+use crate::report::{Event, Events};
+use crate::retry::{backoff, is_permanent};
+use crate::segment::{segment_count, upload_segments};
+use crate::throttle::throttle;
+use crate::transport::Transport;
 
 use anyhow::{anyhow, Result};
-use async_recursion::async_recursion;
+use chrono::{DateTime, Utc};
 use std::{
+    fs::Metadata,
+    io::SeekFrom,
     path::{Path, PathBuf},
     time::Duration,
 };
-use suppaftp::{
-    types::{FileType, FormatControl},
-    AsyncFtpStream,
-};
 use tokio::time::Instant;
-use tokio::{fs::File, io, io::AsyncReadExt, time::sleep};
-use tokio_util::compat::{FuturesAsyncWriteCompatExt, TokioAsyncReadCompatExt};
-use walkdir::DirEntry;
-
-pub fn get_args<'a>() -> Result<&'a Args> {
-    ARG.get().ok_or(anyhow!("Parse args error"))
-}
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+    time::sleep,
+};
 
-pub fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with('.'))
-        .unwrap_or(false)
-}
+/// Suffix of the temporary name used by `--atomic`.
+const PARTIAL_SUFFIX: &str = ".cymo-partial";
 
 /// Connects to an FTP server and changes to a target directory, and returns the current remote directory name.
 ///
 /// # Arguments
 ///
-/// * `ftp_stream` - A mutable reference to an `AsyncFtpStream` that is used to communicate with the
+/// * `ftp_stream` - A mutable reference to an `FtpStream` that is used to communicate with the
 ///   server.
 /// * `i` - The index of the thread that is calling the function.
-/// * `server` - A reference to a string that contains the address of the server.
-/// * `username` - An optional reference to a string that contains the username for authentication.
-/// * `password` - An optional reference to a string that contains the password for authentication.
-/// * `remote_path` - A reference to a string that contains the remote path to change to.
+///
+/// Server, credentials and remote path are read from the options, credentials are
+/// already resolved by `credentials::resolve`.
 ///
 /// # Errors
 ///
 /// This function may return an error if any of the FTP operations fail, such as connecting, logging
 /// in, or changing directory. The error will contain the details of the failure.
 pub async fn connect_and_init(
-    ftp_stream: Result<&mut AsyncFtpStream, &mut anyhow::Error>,
+    ctx: &Context,
+    ftp_stream: Result<&mut Box<dyn Transport>, &mut anyhow::Error>,
     i: usize,
 ) -> Result<()> {
-    let Args {
-        username,
-        password,
+    let Options {
         remote_path,
-        server,
+        connection,
         ..
-    } = get_args()?;
+    } = &ctx.options;
     let ftp_stream = ftp_stream.map_err(|err| anyhow!("{}", err))?;
-    println!("Thread {} connect to {} success", i, server);
-    if let (Some(username), Some(password)) = (&username, &password) {
-        ftp_stream.login(username, password).await?;
-        println!("Thread {} login {} success", i, &server);
+    login(ftp_stream.as_mut(), connection, i, &ctx.events).await?;
+    // Downloads use full remote paths, `--remote-path` may be a file.
+    if !ctx.options.download {
+        ftp_stream.cwd(remote_path).await?;
     }
-    ftp_stream.cwd(&remote_path).await?;
     let current_remote = ftp_stream.pwd().await?;
-    println!("Thread {} current directory: {}", i, &current_remote);
-    if let Some(welcome) = ftp_stream.get_welcome_msg() {
-        println!("{}", welcome);
+    ctx.info(format!(
+        "Thread {} current directory: {}",
+        i, &current_remote
+    ));
+    // Hash command of `--verify` and `--skip-existing checksum`.
+    if ctx.options.verify || ctx.options.skip_existing == Some(SkipPolicy::Checksum) {
+        ctx.select_checksum(ftp_stream.as_mut()).await?;
+    }
+    if let Some(welcome) = ftp_stream.welcome() {
+        ctx.info(welcome);
+    }
+    Ok(())
+}
+
+/// Login with the credentials of the connection.
+///
+/// Without username FTP does not log in, SFTP always authenticates.
+pub async fn login(
+    ftp_stream: &mut dyn Transport,
+    connection: &Connection,
+    i: usize,
+    events: &Events,
+) -> Result<()> {
+    let server = connection.host();
+    events.info(format!("Thread {} connect to {} success", i, server));
+    events.emit(Event::Connected {
+        thread: i,
+        server: server.to_string(),
+    });
+    ftp_stream.login(connection).await?;
+    if connection.username.is_some() {
+        events.info(format!("Thread {} login {} success", i, server));
     }
     Ok(())
 }
 
 /// Changes the remote directory on the FTP server to match the local directory.
 ///
-/// This function takes a mutable reference to an `AsyncFtpStream`, an index `i` that identifies the thread, a reference to a `Path` that represents the local directory, and a reference to a `str` that represents the current remote directory. It returns a `Result<()>` that indicates whether the operation was successful or not.
+/// This function takes a mutable reference to an `FtpStream`, an index `i` that identifies the thread, a reference to a `Path` that represents the local directory, and a reference to a `str` that represents the current remote directory. It returns a `Result<()>` that indicates whether the operation was successful or not.
 ///
-/// This function first extracts the components of the local directory and skips the first one, which is assumed to be the root directory. It then appends these components to the current remote directory and tries to change to it using the `cwd` method of the `AsyncFtpStream`. If the remote directory does not exist, it creates it using the `mkdir` method and then changes to it. It prints a message to indicate the success of the operation.
+/// This function first maps the local directory to the remote one with `remote_dir`. If it is not the current remote directory, it tries to change to it using the `cwd` method of the `FtpStream`. If the remote directory does not exist, it creates it using the `mkdir` method and then changes to it. It prints a message to indicate the success of the operation.
 pub async fn change_remote(
-    ftp_stream: &mut AsyncFtpStream,
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
     i: usize,
     parents: &Path,
     current_remote: &str,
 ) -> Result<()> {
-    // Collect path from params
-    let param_path = PARAM_PATH.get().ok_or(anyhow!("Parse args error"))?;
-    if param_path.is_file() {
+    if ctx.local_root().is_file() {
         return Ok(());
     }
 
-    let param_length = param_path
-        .parent()
-        .unwrap_or(&PathBuf::new())
-        .components()
-        .collect::<Vec<_>>()
-        .len()
-        + 1;
-    // Skip folders from params
-    let parents = parents
-        .components()
-        .collect::<Vec<_>>()
-        .into_iter()
-        .skip(param_length)
-        .collect::<Vec<_>>();
-
     // The final remote path
-    let remote_path = REMOTE_PATH.get().ok_or(anyhow!(""))?;
-    let mut remote = PathBuf::from(&remote_path);
-    remote.push(parents.iter().collect::<PathBuf>());
+    let remote = remote_dir(ctx, parents);
     // If path is same, do not change directory
     if remote.to_string_lossy() == current_remote {
         return Ok(());
     }
+    // Create or change to it.
+    remote_mkdir(ctx, ftp_stream, i, &remote.to_string_lossy()).await?;
+    Ok(())
+}
 
-    let len = parents.len();
-    if len == 0 {
-        remote_mkdir(ftp_stream, i, &remote_path.to_string_lossy()).await?;
-        return Ok(());
+/// Map a local directory to the remote directory it will be uploaded into.
+///
+/// The folders from params are skipped, the rest of local directory is appended
+/// to the remote path. When the local path from params is a file, it is the remote path.
+pub fn remote_dir(ctx: &Context, parents: &Path) -> PathBuf {
+    let param_path = ctx.local_root();
+    let mut remote = ctx.remote_root().to_path_buf();
+    if param_path.is_file() {
+        return remote;
     }
-    let local_path = &parents.iter().collect::<PathBuf>();
-    // Current remote directory
-    let mut remote = PathBuf::from(&remote_path);
-    remote.push(local_path);
-    let remote = remote.to_string_lossy();
-    // Current local directory
-    if local_path.to_string_lossy().len() != 0 {
-        // Create or change to it.
-        remote_mkdir(ftp_stream, i, &remote).await?;
+
+    let param_length = param_path
+        .parent()
+        .unwrap_or(&PathBuf::new())
+        .components()
+        .count()
+        + 1;
+    // Skip folders from params
+    let local_path = parents.components().skip(param_length).collect::<PathBuf>();
+    if !local_path.as_os_str().is_empty() {
+        remote.push(local_path);
     }
-    Ok(())
+    remote
 }
 
 /// Change into target remote directory.
 /// And create it if not exist.
-pub async fn remote_mkdir(ftp_stream: &mut AsyncFtpStream, i: usize, remote: &str) -> Result<()> {
+pub async fn remote_mkdir(
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
+    i: usize,
+    remote: &str,
+) -> Result<()> {
     // Create or change to it.
-    match ftp_stream.cwd(&remote).await {
+    match ftp_stream.cwd(remote).await {
         Ok(_) => {
             let remote = ftp_stream.pwd().await?;
-            println!("Thread {} change directory to {} success", i, remote);
+            ctx.info(format!(
+                "Thread {} change directory to {} success",
+                i, remote
+            ));
         }
         Err(_) => {
-            ftp_stream.mkdir(&remote).await?;
-            println!("Thread {} create directory {} success", i, remote);
-            ftp_stream.cwd(&remote).await?;
-            println!("Thread {} change directory to {} success", i, remote);
+            ftp_stream.mkdir(remote).await?;
+            ctx.info(format!("Thread {} create directory {} success", i, remote));
+            ctx.emit(Event::Mkdir {
+                thread: i,
+                path: remote.to_string(),
+            });
+            ftp_stream.cwd(remote).await?;
+            ctx.info(format!(
+                "Thread {} change directory to {} success",
+                i, remote
+            ));
         }
     }
     Ok(())
@@ -150,17 +182,39 @@ pub async fn remote_mkdir(ftp_stream: &mut AsyncFtpStream, i: usize, remote: &st
 
 /// Uploads a local file to the FTP server.
 ///
-/// This function takes a mutable reference to an `AsyncFtpStream`, an index `i` that identifies the thread, a reference to a `Path` that represents the local file, and a reference to a `str` that represents the current remote directory. It returns a `Result<()>` that indicates whether the operation was successful or not.
+/// This function takes a mutable reference to an `FtpStream`, an index `i` that identifies the thread, a reference to a `Path` that represents the local file, and a flag `resume` that allows continuing a partially uploaded file. It returns a `Result<()>` that indicates whether the operation was successful or not.
+///
+/// This function first extracts the file name and the parent directories of the local file. It then calls the `change_remote` function to ensure that the remote directory exists and matches the local directory. It then opens the local file using `File::open` and streams it to the server using `Transport::put`. When `resume` is set and the server already has a smaller copy written after the local file last changed, it seeks the local file to that size and only sends the rest, FTP continues with `REST` + `STOR`, or `APPE` when `REST` is refused. It prints a message to indicate the success of the operation.
+///
+/// With `--atomic` the content goes to `.name.cymo-partial` first and is renamed to the
+/// final name after the transfer finished, so the final file is never half written.
+/// Retries and `--resume` continue the same temporary file.
 ///
-/// This function first extracts the file name and the parent directories of the local file. It then calls the `change_remote` function to ensure that the remote directory exists and matches the local directory. It then opens the local file using `File::open` and creates a data stream for uploading using `put_with_stream`. It copies the bytes from the local file to the data stream using `io::copy` and finalizes the upload using `finalize_put_stream`. It prints a message to indicate the success of the operation.
+/// With `--verify` the uploaded file is compared with the local file before it is
+/// renamed, a mismatch fails the attempt.
 ///
-pub async fn upload_files(ftp_stream: &mut AsyncFtpStream, i: usize, path: &Path) -> Result<()> {
+/// Files selected by `segment_count` are sent in parts over extra connections by
+/// `upload_segments`, they are never resumed.
+pub async fn upload_files(
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
+    i: usize,
+    path: &Path,
+    resume: bool,
+) -> Result<()> {
+    let Options { atomic, verify, .. } = &ctx.options;
     // Current local file filename
     let filename = path
         .file_name()
         .ok_or(anyhow!("read file name failed"))?
         .to_str()
         .ok_or(anyhow!("read file name failed"))?;
+    // Name actually written
+    let target = if *atomic {
+        partial_name(filename)
+    } else {
+        filename.to_string()
+    };
 
     let current_remote = ftp_stream.pwd().await?;
     // Current local file parent directories
@@ -168,7 +222,7 @@ pub async fn upload_files(ftp_stream: &mut AsyncFtpStream, i: usize, path: &Path
     // Check remote directory exists
     // And change into it.
     if let Some(parents) = parents {
-        change_remote(ftp_stream, i, parents, &current_remote).await?;
+        change_remote(ctx, ftp_stream, i, parents, &current_remote).await?;
     }
     // Upload files
     // https://docs.rs/suppaftp/latest/suppaftp/types/enum.FileType.html#
@@ -176,71 +230,286 @@ pub async fn upload_files(ftp_stream: &mut AsyncFtpStream, i: usize, path: &Path
     let mut local = File::open(&path).await?;
     // Detect file type
     let mut magic_number = [0u8; 16];
-    if local.read_exact(&mut magic_number).await.is_ok() {
-        let is_text = String::from_utf8(magic_number.into());
-        if is_text.is_ok() {
-            ftp_stream
-                .transfer_type(FileType::Ascii(FormatControl::Default))
-                .await?;
-        } else {
-            ftp_stream.transfer_type(FileType::Binary).await?;
-        }
+    let mut is_binary = false;
+    if *verify {
+        // Hash and size only match a byte for byte copy, ASCII mode may convert line endings.
+        is_binary = true;
+        ftp_stream.set_binary(true).await?;
+    } else if local.read_exact(&mut magic_number).await.is_ok() {
+        is_binary = String::from_utf8(magic_number.into()).is_err();
+        ftp_stream.set_binary(is_binary).await?;
     };
 
-    println!("Thread {} uploading {:?}", i, &path);
     let mut local = File::open(&path).await?;
-    let size_kb = local.metadata().await?.len() / 1000;
+    let metadata = local.metadata().await?;
+    let segments = segment_count(ctx, ftp_stream, &metadata, is_binary).await;
+    // ASCII mode may convert line endings, remote size can not be used as offset.
+    // Segments leave holes, the size on server is not what was written.
+    let offset = if resume && is_binary && segments == 1 {
+        resume_offset(ftp_stream, &target, &metadata).await
+    } else {
+        0
+    };
+    let sent = metadata.len() - offset;
+    let mut progress = ctx.start(i, path, metadata.len());
+    progress.skip_to(offset);
     let now = Instant::now();
     // Stream file content to ftp server
-    let mut remote = ftp_stream.put_with_stream(filename).await?.compat_write();
-    io::copy(&mut local, &mut remote).await?;
-    ftp_stream.finalize_put_stream(remote.compat()).await?;
-    // duration in milliseconds
-    let duration = (now.elapsed().as_millis() as f64).round();
-    // kb per second
-    let speed = (size_kb as f64 / duration) * 1000.0;
-    let speed = if speed > 1024.0 {
-        format!("{:.1}MB/s", (speed / 1000.0).round())
+    if offset > 0 {
+        ctx.info(format!(
+            "Thread {} resuming {:?} from {} bytes",
+            i, &path, offset
+        ));
+        local.seek(SeekFrom::Start(offset)).await?;
+    } else if segments > 1 {
+        ctx.info(format!(
+            "Thread {} uploading {:?} in {} segments",
+            i, &path, segments
+        ));
     } else {
-        format!("{:.1}KB/s", speed.round())
-    };
-    println!("Thread {} upload {:?} success {}", i, &path, speed);
+        ctx.info(format!("Thread {} uploading {:?}", i, &path));
+    }
+    if segments > 1 {
+        upload_segments(ctx, ftp_stream, i, path, &target, &progress, segments).await?;
+    } else {
+        ftp_stream
+            .put(&target, &mut throttle(ctx, progress.reader(local)), offset)
+            .await?;
+    }
+    // Verify and rename are not part of the transfer.
+    let speed = format_speed(sent, now.elapsed());
+    if *verify {
+        // A broken file can not be resumed, the retry uploads it again from start.
+        match checksum::verify(ftp_stream, ctx.checksum(), path, &target).await {
+            Ok(by) => ctx.info(format!("Thread {} verify {:?} by {} success", i, path, by)),
+            Err(err) => {
+                let _ = ftp_stream.rm(&target).await;
+                return Err(err);
+            }
+        }
+    }
+    if *atomic {
+        replace(ftp_stream, &target, filename).await?;
+    }
+    progress.finish();
+    ctx.info(format!("Thread {} upload {:?} success {}", i, &path, speed));
     Ok(())
 }
 
-/// TODO show file upload speed
-#[async_recursion(?Send)]
-pub async fn upload(
-    ftp_stream: &mut AsyncFtpStream,
+/// Temporary name of a file uploaded with `--atomic`, hidden in the same directory.
+pub fn partial_name(filename: &str) -> String {
+    format!(".{}{}", filename, PARTIAL_SUFFIX)
+}
+
+/// Whether a remote file name is a temporary file of `--atomic`.
+pub fn is_partial(filename: &str) -> bool {
+    filename.starts_with('.') && filename.ends_with(PARTIAL_SUFFIX)
+}
+
+/// Rename `from` to `to` over the existing file.
+///
+/// Servers refusing to rename onto an existing file, like most SFTP servers, get `to`
+/// removed first. The final file is missing for a moment in that case.
+async fn replace(ftp_stream: &mut dyn Transport, from: &str, to: &str) -> Result<()> {
+    if ftp_stream.rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    let _ = ftp_stream.rm(to).await;
+    ftp_stream.rename(from, to).await
+}
+
+/// Remove the temporary file of a failed `--atomic` upload.
+///
+/// Errors are ignored since the connection may be broken, the next upload of the file
+/// replaces it and `--delete` removes it.
+async fn remove_partial(ctx: &Context, ftp_stream: &mut dyn Transport, i: usize, path: &Path) {
+    let (Some(parents), Some(filename)) = (path.parent(), path.file_name()) else {
+        return;
+    };
+    let remote = remote_dir(ctx, parents);
+    let partial = remote.join(partial_name(&filename.to_string_lossy()));
+    if ftp_stream.rm(&partial.to_string_lossy()).await.is_ok() {
+        ctx.info(format!("Thread {} remove {:?}", i, partial));
+    }
+}
+
+/// Downloads a remote file from the FTP server.
+///
+/// The local file is the remote path relative to `--remote-path`, placed under `--local-path`.
+/// Its parent directories are created before the download starts. Files are always
+/// retrieved in binary mode, so the local file has exactly the same bytes as the server.
+///
+/// The content goes to `.name.cymo-partial` beside the local file first and is renamed
+/// over it when finished, a failed download leaves an existing local file untouched.
+pub async fn download_files(
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
     i: usize,
     path: &Path,
-    retry_times: u32,
 ) -> Result<()> {
-    let Args { retry, .. } = get_args()?;
-    return match upload_files(ftp_stream, i, path).await {
-        Ok(res) => Ok(res),
-        Err(err) => match retry {
-            Some(times) => {
-                if retry_times >= *times {
-                    return Err(err);
-                }
-                sleep_with_seconds(3, format!("Thread {} file {:?}", i, path).into()).await;
-                upload(ftp_stream, i, path, retry_times + 1).await
-            }
-            None => Err(err),
-        },
+    let local_path = local_file(ctx, path);
+    let filename = local_path
+        .file_name()
+        .ok_or(anyhow!("read file name failed"))?
+        .to_string_lossy();
+    let partial = local_path.with_file_name(partial_name(&filename));
+    ftp_stream.set_binary(true).await?;
+
+    ctx.info(format!("Thread {} downloading {:?}", i, &path));
+    let remote = path.to_string_lossy();
+    let size = ftp_stream.size(&remote).await.unwrap_or(0);
+    let progress = ctx.start(i, path, size);
+    let now = Instant::now();
+    let local = File::create(&partial).await?;
+    // Stream ftp server content to file
+    let received = match ftp_stream
+        .get(&remote, &mut throttle(ctx, progress.writer(local)))
+        .await
+    {
+        Ok(received) => received,
+        Err(err) => {
+            let _ = fs::remove_file(&partial).await;
+            return Err(err);
+        }
+    };
+    fs::rename(&partial, &local_path).await?;
+    let speed = format_speed(received, now.elapsed());
+    progress.finish();
+    ctx.info(format!(
+        "Thread {} download {:?} success {}",
+        i, &path, speed
+    ));
+    Ok(())
+}
+
+/// Map a remote file to the local file it will be downloaded into.
+///
+/// When `--remote-path` is the file itself, it is put under `--local-path` by its name.
+pub fn local_file(ctx: &Context, remote: &Path) -> PathBuf {
+    let relative = match remote.strip_prefix(ctx.remote_root()) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        Ok(_) => remote.file_name().map(Path::new).unwrap_or(Path::new("")),
+        Err(_) => remote,
     };
+    ctx.local_root().join(relative)
+}
+
+/// Transfer speed in KB/s or MB/s, 1024 based like `--limit-rate`.
+fn format_speed(bytes: u64, elapsed: Duration) -> String {
+    // Tiny files may finish within the clock resolution.
+    let seconds = elapsed.as_secs_f64().max(0.001);
+    let speed = bytes as f64 / seconds / 1024.0;
+    if speed >= 1024.0 {
+        format!("{:.1}MB/s", speed / 1024.0)
+    } else {
+        format!("{:.1}KB/s", speed)
+    }
 }
 
-/// Sleep current thread and print count
+/// Find the offset to continue a partially uploaded file.
 ///
-/// Argments:
+/// Returns `0` when the remote file not exist, is not smaller than the local file,
+/// or its modify time can not prove the local file is unchanged since it was written.
+async fn resume_offset(ftp_stream: &mut dyn Transport, filename: &str, metadata: &Metadata) -> u64 {
+    let remote_size = match ftp_stream.size(filename).await {
+        Ok(size) => size,
+        Err(_) => return 0,
+    };
+    if remote_size == 0 || remote_size >= metadata.len() {
+        return 0;
+    }
+    let (Ok(remote_modified), Ok(local_modified)) =
+        (ftp_stream.mdtm(filename).await, metadata.modified())
+    else {
+        return 0;
+    };
+    // MDTM is in UTC and only has seconds.
+    let local_modified: DateTime<Utc> = local_modified.into();
+    if remote_modified.timestamp() < local_modified.timestamp() {
+        return 0;
+    }
+    remote_size
+}
+
+/// Upload or download a file over the connection of the thread, retried `--retry` times.
 ///
-/// - `duration`: duration for sleep, seconds
-async fn sleep_with_seconds(duration: usize, message: Option<String>) {
-    let message = message.map(|m| format!("{} ", m)).unwrap_or("".into());
-    for i in 1..=duration {
-        println!("{}will retry in {}s", message, duration - i);
-        sleep(Duration::from_secs(1)).await;
+/// Permanent errors like `550` fail the file at once. A lost connection is opened again and the file retried, up to `--reconnect` times
+/// without using up `--retry`. The thread connects first when it has no connection.
+pub async fn transfer(
+    ctx: &Context,
+    session: &mut Option<Box<dyn Transport>>,
+    i: usize,
+    path: &Path,
+) -> Result<()> {
+    let Options {
+        connection,
+        resume,
+        atomic,
+        download,
+        ..
+    } = &ctx.options;
+    let mut retry_times = 0;
+    // Connection attempts of this file, lost connections and failed connects together.
+    let mut reconnects = 0;
+    loop {
+        let ftp_stream = match session {
+            Some(ftp_stream) => ftp_stream,
+            None => session.insert(connect_session(ctx, i, &mut reconnects).await?),
+        };
+        let result = if *download {
+            download_files(ctx, ftp_stream.as_mut(), i, path).await
+        } else {
+            // Retries always continue from what the previous attempt left on server.
+            let retrying = retry_times > 0 || reconnects > 0;
+            upload_files(ctx, ftp_stream.as_mut(), i, path, *resume || retrying).await
+        };
+        let err = match result {
+            Ok(res) => return Ok(res),
+            Err(err) => err,
+        };
+        let disconnected = is_disconnected(&err, ftp_stream.as_ref());
+        if disconnected {
+            *session = None;
+            if reconnects < connection.reconnect {
+                *session = Some(reconnect_session(ctx, i, &err, &mut reconnects).await?);
+                continue;
+            }
+        }
+        if disconnected || !is_permanent(&err) {
+            if let Some(times) = connection.retry.filter(|times| retry_times < *times) {
+                wait_retry(ctx, i, path, &err, retry_times, times).await;
+                retry_times += 1;
+                continue;
+            }
+        }
+        // Kept for the next run to continue with `--resume`.
+        if let (false, true, false, Some(ftp_stream)) =
+            (*download, *atomic, *resume, session.as_mut())
+        {
+            remove_partial(ctx, ftp_stream.as_mut(), i, path).await;
+        }
+        return Err(err);
     }
 }
+
+/// Wait before retry `retry_times + 1` of `times`, by the backoff of `--retry-delay`.
+async fn wait_retry(
+    ctx: &Context,
+    i: usize,
+    path: &Path,
+    err: &anyhow::Error,
+    retry_times: u32,
+    times: u32,
+) {
+    let delay = backoff(&ctx.options.connection, retry_times);
+    ctx.warn(format!(
+        "Thread {} file {:?} failed {}, retry {}/{} in {:.1}s",
+        i,
+        path,
+        err,
+        retry_times + 1,
+        times,
+        delay.as_secs_f64()
+    ));
+    sleep(delay).await;
+}
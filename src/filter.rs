@@ -0,0 +1,199 @@
+use crate::{options::Options, report::Events};
+
+use anyhow::Result;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// Ignore file name, gitignore syntax, can be placed at any level of the local path.
+const IGNORE_FILE: &str = ".cymoignore";
+
+/// Decide which local files and directories are uploaded.
+///
+/// - Hidden files and directories are excluded unless `--hidden`.
+/// - `--exclude` globs exclude matched files and directories.
+/// - `.cymoignore` files exclude matched entries under its directory, the deepest one wins.
+/// - When any `--include` is given, only files matched by it are uploaded.
+///
+/// All globs use gitignore syntax, `--include` and `--exclude` are relative to the local path.
+pub struct Filter {
+    root: PathBuf,
+    hidden: bool,
+    include: Option<Gitignore>,
+    exclude: Gitignore,
+    /// `.cymoignore` in every directory, parents before children.
+    ignores: Vec<Gitignore>,
+}
+
+impl Filter {
+    /// Ignore files failed to read are warned through `events`, their valid lines still apply.
+    pub fn new(options: &Options, events: &Events) -> Result<Self> {
+        let root = PathBuf::from(&options.local_path);
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(build_globs(&root, &options.include)?)
+        };
+        let mut filter = Self {
+            exclude: build_globs(&root, &options.exclude)?,
+            root,
+            hidden: options.hidden,
+            include,
+            ignores: vec![],
+        };
+
+        // Find all ignore files, skip directories already excluded.
+        let ignore_files = WalkDir::new(&filter.root)
+            .into_iter()
+            .filter_entry(|e| {
+                !e.file_type().is_dir() || !filter.is_excluded_by_args(e.path(), true)
+            })
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.file_name() == IGNORE_FILE)
+            .map(|e| e.into_path())
+            .collect::<Vec<_>>();
+        for file in ignore_files {
+            let (gitignore, err) = Gitignore::new(&file);
+            if let Some(err) = err {
+                events.warn(format!("Read {:?} failed {}", file, err));
+            }
+            filter.ignores.push(gitignore);
+        }
+        Ok(filter)
+    }
+
+    /// Walk the local path, excluded directories are not entered.
+    pub fn walk(&self) -> impl Iterator<Item = DirEntry> + '_ {
+        WalkDir::new(&self.root)
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !self.is_excluded(e.path(), e.file_type().is_dir()))
+            .filter_map(|e| e.ok())
+    }
+
+    /// Whether a path under the local path is excluded.
+    ///
+    /// Parents are not checked, the caller should not walk into excluded directories.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if path == self.root {
+            return false;
+        }
+        if path.file_name().is_some_and(|name| name == IGNORE_FILE) {
+            return true;
+        }
+        if self.is_excluded_by_args(path, is_dir) {
+            return true;
+        }
+        for ignore in self.ignores.iter().rev() {
+            if !path.starts_with(ignore.path()) {
+                continue;
+            }
+            match ignore.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => break,
+            }
+        }
+        match &self.include {
+            Some(include) if !is_dir => {
+                !include.matched_path_or_any_parents(path, false).is_ignore()
+            }
+            _ => false,
+        }
+    }
+
+    /// Check `--hidden` and `--exclude`.
+    fn is_excluded_by_args(&self, path: &Path, is_dir: bool) -> bool {
+        if path == self.root {
+            return false;
+        }
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        (!self.hidden && hidden) || self.exclude.matched(path, is_dir).is_ignore()
+    }
+}
+
+/// Build globs from options, one glob per line of gitignore.
+fn build_globs(root: &Path, globs: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for glob in globs {
+        builder.add_line(None, glob)?;
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A local tree with `.cymoignore` files, and a filter over it.
+    fn filter(options: Options) -> (TempDir, Filter) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs/drafts")).unwrap();
+        fs::write(dir.path().join(IGNORE_FILE), "*.log\n").unwrap();
+        fs::write(
+            dir.path().join("docs").join(IGNORE_FILE),
+            "drafts/\n!keep.log\n",
+        )
+        .unwrap();
+        let options = Options {
+            local_path: dir.path().to_string_lossy().to_string(),
+            ..options
+        };
+        let filter = Filter::new(&options, &Events::default()).unwrap();
+        (dir, filter)
+    }
+
+    #[test]
+    fn excluded() {
+        let (dir, filter) = filter(Options {
+            exclude: vec!["node_modules".to_string(), "*.map".to_string()],
+            ..Options::default()
+        });
+        let excluded = |path: &str, is_dir| filter.is_excluded(&dir.path().join(path), is_dir);
+        assert!(!excluded("", true));
+        assert!(!excluded("index.html", false));
+        assert!(excluded("app.js.map", false));
+        assert!(excluded("web/node_modules", true));
+        assert!(excluded(".env", false));
+        assert!(excluded(".git", true));
+        assert!(excluded(IGNORE_FILE, false));
+        assert!(excluded("error.log", false));
+        assert!(excluded("docs/error.log", false));
+        assert!(excluded("docs/drafts", true));
+        // The deepest ignore file wins.
+        assert!(!excluded("docs/keep.log", false));
+        assert!(!excluded("docs/drafts", false));
+    }
+
+    #[test]
+    fn hidden() {
+        let (dir, filter) = filter(Options {
+            hidden: true,
+            ..Options::default()
+        });
+        assert!(!filter.is_excluded(&dir.path().join(".htaccess"), false));
+        assert!(filter.is_excluded(&dir.path().join(IGNORE_FILE), false));
+    }
+
+    #[test]
+    fn included() {
+        let (dir, filter) = filter(Options {
+            include: vec!["*.html".to_string(), "assets/".to_string()],
+            ..Options::default()
+        });
+        let excluded = |path: &str, is_dir| filter.is_excluded(&dir.path().join(path), is_dir);
+        assert!(!excluded("index.html", false));
+        assert!(!excluded("docs/a.html", false));
+        assert!(!excluded("assets/app.js", false));
+        assert!(excluded("app.js", false));
+        // Directories are walked, files in them are checked.
+        assert!(!excluded("docs", true));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{
+    options::{Connection, TlsMode},
+    remote::RemoteEntry,
+    retry::Permanent,
+    transport::Transport,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::str::FromStr;
+use suppaftp::{
+    async_native_tls::{Certificate, TlsConnector},
+    list::File as ListFile,
+    types::{FileType, FormatControl},
+    AsyncNativeTlsConnector, AsyncNativeTlsFtpStream,
+};
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
+
+/// FTP connection used by all threads, upgraded to FTPS when `--tls` is set.
+pub type FtpStream = AsyncNativeTlsFtpStream;
+
+/// Connect to the FTP server.
+///
+/// With `--tls explicit` the control connection is upgraded by `AUTH TLS`, with
+/// `--tls implicit` the TLS handshake is done right after TCP connected. Both
+/// send `PBSZ 0` and `PROT P`, so data connections are encrypted too.
+pub async fn connect(connection: &Connection) -> Result<FtpStream> {
+    let addr = connection.addr();
+    let ftp_stream = match connection.tls {
+        TlsMode::None => FtpStream::connect(addr).await?,
+        TlsMode::Explicit => {
+            FtpStream::connect(addr)
+                .await?
+                .into_secure(tls_connector(connection)?, connection.host())
+                .await?
+        }
+        TlsMode::Implicit => {
+            FtpStream::connect_secure_implicit(addr, tls_connector(connection)?, connection.host())
+                .await?
+        }
+    };
+    Ok(ftp_stream)
+}
+
+/// Build TLS connector with custom CA certificates and `--insecure`.
+fn tls_connector(connection: &Connection) -> Result<AsyncNativeTlsConnector> {
+    let mut connector = TlsConnector::new();
+    if let Some(ca_file) = &connection.ca_file {
+        let bundle = std::fs::read_to_string(ca_file)
+            .map_err(|err| Permanent(format!("Read CA file {:?} failed {}", ca_file, err)))?;
+        // A bundle may contain many certificates, add them one by one.
+        let end = "-----END CERTIFICATE-----";
+        for pem in bundle.split_inclusive(end).filter(|pem| pem.contains(end)) {
+            let certificate = Certificate::from_pem(pem.as_bytes())
+                .map_err(|err| Permanent(format!("Read CA file {:?} failed {}", ca_file, err)))?;
+            connector = connector.add_root_certificate(certificate);
+        }
+    }
+    if connection.insecure {
+        connector = connector
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    Ok(AsyncNativeTlsConnector::from(connector))
+}
+
+// Inherent methods of `FtpStream` have the same names, they are called inside.
+#[async_trait]
+impl Transport for FtpStream {
+    /// Without username the server is used anonymously, without logging in.
+    async fn login(&mut self, connection: &Connection) -> Result<()> {
+        match (&connection.username, &connection.password) {
+            (Some(username), Some(password)) => Ok(self.login(username, password).await?),
+            (None, None) => Ok(()),
+            (Some(username), None) => {
+                Err(Permanent(format!("No password of {} to login", username)).into())
+            }
+            (None, Some(_)) => {
+                Err(Permanent("No username to login with the password".to_string()).into())
+            }
+        }
+    }
+
+    fn welcome(&self) -> Option<&str> {
+        self.get_welcome_msg()
+    }
+
+    async fn cwd(&mut self, path: &str) -> Result<()> {
+        Ok(self.cwd(path).await?)
+    }
+
+    async fn pwd(&mut self) -> Result<String> {
+        Ok(self.pwd().await?)
+    }
+
+    async fn mkdir(&mut self, path: &str) -> Result<()> {
+        Ok(self.mkdir(path).await?)
+    }
+
+    async fn rm(&mut self, path: &str) -> Result<()> {
+        Ok(self.rm(path).await?)
+    }
+
+    async fn rmdir(&mut self, path: &str) -> Result<()> {
+        Ok(self.rmdir(path).await?)
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        Ok(self.rename(from, to).await?)
+    }
+
+    async fn size(&mut self, path: &str) -> Result<u64> {
+        Ok(self.size(path).await? as u64)
+    }
+
+    async fn mdtm(&mut self, path: &str) -> Result<DateTime<Utc>> {
+        Ok(self.mdtm(path).await?.and_utc())
+    }
+
+    /// `MLSD` is used first, it has exact size and modify time. For servers without it,
+    /// fall back to `LIST`, size and modify time are left to `stat`.
+    async fn list_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>> {
+        if let Ok(lines) = self.mlsd(Some(path)).await {
+            return Ok(lines.iter().filter_map(|line| parse_mlsd(line)).collect());
+        }
+        let entries = self
+            .list(Some(path))
+            .await?
+            .iter()
+            .filter_map(|line| ListFile::from_str(line).ok())
+            .filter(|file| file.name() != "." && file.name() != "..")
+            .map(|file| RemoteEntry {
+                name: file.name().to_string(),
+                is_dir: file.is_directory(),
+                size: None,
+                modified: None,
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    async fn set_binary(&mut self, binary: bool) -> Result<()> {
+        let file_type = if binary {
+            FileType::Binary
+        } else {
+            FileType::Ascii(FormatControl::Default)
+        };
+        Ok(self.transfer_type(file_type).await?)
+    }
+
+    /// Resume with `REST` + `STOR`, or `APPE` when `REST` is refused.
+    async fn put(
+        &mut self,
+        path: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        offset: u64,
+    ) -> Result<u64> {
+        let remote = if offset > 0 {
+            match self.resume_transfer(offset as usize).await {
+                Ok(_) => self.put_with_stream(path).await?,
+                // APPE writes at the end, only right when the end is the offset.
+                Err(err) if self.size(path).await.ok() != Some(offset as usize) => {
+                    return Err(err.into())
+                }
+                Err(_) => self.append_with_stream(path).await?,
+            }
+        } else {
+            self.put_with_stream(path).await?
+        };
+        let mut remote = remote.compat_write();
+        let size = io::copy(reader, &mut remote).await?;
+        self.finalize_put_stream(remote.into_inner()).await?;
+        Ok(size)
+    }
+
+    async fn get(
+        &mut self,
+        path: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<u64> {
+        let mut remote = self.retr_as_stream(path).await?.compat();
+        let size = io::copy(&mut remote, writer).await?;
+        self.finalize_retr_stream(remote.into_inner()).await?;
+        Ok(size)
+    }
+
+    async fn quit(&mut self) -> Result<()> {
+        Ok(self.quit().await?)
+    }
+
+    fn as_ftp(&mut self) -> Option<&mut FtpStream> {
+        Some(self)
+    }
+}
+
+/// Parse a `MLSD` line like `type=file;size=1024;modify=20240110120000; index.html`.
+///
+/// Current and parent directory (`cdir`, `pdir`) and links are dropped.
+fn parse_mlsd(line: &str) -> Option<RemoteEntry> {
+    let (facts, name) = line.split_once(' ')?;
+    let mut entry = RemoteEntry {
+        name: name.to_string(),
+        is_dir: false,
+        size: None,
+        modified: None,
+    };
+    for fact in facts.split(';') {
+        let Some((key, value)) = fact.split_once('=') else {
+            continue;
+        };
+        match key.to_lowercase().as_str() {
+            "type" => match value.to_lowercase().as_str() {
+                "file" => entry.is_dir = false,
+                "dir" => entry.is_dir = true,
+                _ => return None,
+            },
+            "size" => entry.size = value.parse().ok(),
+            // Modify time may have milliseconds, like 20240110120000.123
+            "modify" => {
+                entry.modified = value
+                    .get(..14)
+                    .and_then(|time| NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S").ok())
+                    .map(|time| time.and_utc())
+            }
+            _ => {}
+        }
+    }
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mlsd_file() {
+        let entry = parse_mlsd("type=file;size=1024;modify=20240110120000; index.html").unwrap();
+        assert_eq!(entry.name, "index.html");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.size, Some(1024));
+        let modified = entry.modified.unwrap();
+        assert_eq!(modified.to_rfc3339(), "2024-01-10T12:00:00+00:00");
+    }
+
+    #[test]
+    fn mlsd_facts() {
+        // Fact names are case insensitive, modify time may have milliseconds.
+        let entry = parse_mlsd("Type=DIR;Modify=20240110120000.123;UNIX.mode=0755; a b").unwrap();
+        assert_eq!(entry.name, "a b");
+        assert!(entry.is_dir);
+        assert_eq!(entry.size, None);
+        assert!(entry.modified.is_some());
+        let entry = parse_mlsd("size=x;modify=yesterday; a").unwrap();
+        assert_eq!((entry.size, entry.modified), (None, None));
+    }
+
+    #[test]
+    fn mlsd_skipped() {
+        assert!(parse_mlsd("type=cdir; .").is_none());
+        assert!(parse_mlsd("type=pdir; ..").is_none());
+        assert!(parse_mlsd("type=OS.unix=slink:/target; link").is_none());
+        assert!(parse_mlsd("garbage").is_none());
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::Notify;
+
+/// Stop requests of a run, shared by its threads.
+#[derive(Default)]
+pub struct Stop {
+    /// `1` stops sending new files, `2` aborts files in progress.
+    level: AtomicUsize,
+    /// Wakes transfers in progress on abort.
+    abort: Notify,
+}
+
+impl Stop {
+    /// Whether new files should not be started.
+    pub fn is_stopping(&self) -> bool {
+        self.level.load(Ordering::SeqCst) >= 1
+    }
+
+    /// Whether files in progress were aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.level.load(Ordering::SeqCst) >= 2
+    }
+
+    /// Complete when files in progress are aborted, used to cancel a transfer.
+    pub async fn aborted(&self) {
+        loop {
+            // Registered before the check, an abort in between is not missed.
+            let notified = self.abort.notified();
+            if self.is_aborted() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Stop a run from another thread, like a Ctrl-C handler.
+///
+/// Pending and failed files are written into `--journal` when the run was stopped.
+#[derive(Clone)]
+pub struct StopHandle(pub(crate) Arc<Stop>);
+
+impl StopHandle {
+    /// Stop sending new files, files in progress finish.
+    pub fn stop(&self) {
+        self.0.level.fetch_max(1, Ordering::SeqCst);
+    }
+
+    /// Abort files in progress, they are recorded as pending.
+    pub fn abort(&self) {
+        self.0.level.fetch_max(2, Ordering::SeqCst);
+        self.0.abort.notify_waiters();
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Files of a run by their result, written when the run is interrupted.
+///
+/// `--resume-journal` reads it to continue only the pending and failed files.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Journal {
+    pub action: String,
+    pub local_path: String,
+    pub remote_path: String,
+    pub completed: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+    /// Files never started, or aborted by the second Ctrl-C
+    pub pending: Vec<PathBuf>,
+}
+
+impl Journal {
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Read journal {:?} failed {}", path, err))?;
+        serde_json::from_str(&content)
+            .map_err(|err| anyhow!("Parse journal {:?} failed {}", path, err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .map_err(|err| anyhow!("Write journal {:?} failed {}", path, err))
+    }
+
+    /// Remove a journal left by a run, a missing one is fine.
+    pub fn remove(path: &Path) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(anyhow!("Remove journal {:?} failed {}", path, err))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Files to transfer again.
+    pub fn remaining(&self) -> HashSet<PathBuf> {
+        self.pending.iter().chain(&self.failed).cloned().collect()
+    }
+}
+
+/// Read paths of `--files-from`, one per line, `-` reads stdin.
+///
+/// Paths are relative to `base` or start with it, like the failed list. They are returned
+/// under `base` like found by walking it, so they map to the same remote directories.
+pub fn read_list(source: &Path, base: &str) -> Result<Vec<PathBuf>> {
+    let content = if source == Path::new("-") {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(source)
+    }
+    .map_err(|err| anyhow!("Read file list {:?} failed {}", source, err))?;
+    content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let path = Path::new(line);
+            let relative = path.strip_prefix(base).unwrap_or(path);
+            if relative.is_absolute()
+                || relative
+                    .components()
+                    .any(|component| component == Component::ParentDir)
+            {
+                return Err(anyhow!("{} in {:?} is not in {}", line, source, base));
+            }
+            if relative.as_os_str().is_empty() {
+                return Ok(PathBuf::from(base));
+            }
+            Ok(Path::new(base).join(relative))
+        })
+        .collect()
+}
+
+/// Write paths one per line, the format of `--files-from`.
+pub fn write_list(path: &Path, files: &[PathBuf]) -> Result<()> {
+    let content = files
+        .iter()
+        .map(|file| format!("{}\n", file.to_string_lossy()))
+        .collect::<String>();
+    fs::write(path, content).map_err(|err| anyhow!("Write file list {:?} failed {}", path, err))
+}
+
+/// Remove a file list, no error when it does not exist.
+pub fn remove_list(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(anyhow!("Remove file list {:?} failed {}", path, err))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(content: &str, base: &str) -> Result<Vec<PathBuf>> {
+        let dir = tempfile::tempdir()?;
+        let list = dir.path().join("list.txt");
+        fs::write(&list, content)?;
+        read_list(&list, base)
+    }
+
+    #[test]
+    fn list_relative_or_under_base() {
+        let files = read("a.txt\r\n\nsite/b/c.txt\nb/d.txt\n", "site").unwrap();
+        let expected = ["site/a.txt", "site/b/c.txt", "site/b/d.txt"].map(PathBuf::from);
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn list_of_single_file() {
+        let files = read("/data/a.txt\n", "/data/a.txt").unwrap();
+        assert_eq!(files, [PathBuf::from("/data/a.txt")]);
+    }
+
+    #[test]
+    fn list_outside_base() {
+        assert!(read("../secret\n", "site").is_err());
+        assert!(read("/etc/passwd\n", "site").is_err());
+        assert!(read("a/../../b\n", "site").is_err());
+    }
+
+    #[test]
+    fn list_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("failed.txt");
+        remove_list(&list).unwrap();
+        write_list(&list, &[PathBuf::from("a")]).unwrap();
+        remove_list(&list).unwrap();
+        assert!(!list.exists());
+    }
+
+    #[test]
+    fn list_written_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("failed.txt");
+        let files = ["site/a b.txt", "site/c/d.txt"].map(PathBuf::from);
+        write_list(&list, &files).unwrap();
+        assert_eq!(read_list(&list, "site").unwrap(), files);
+    }
+}
@@ -0,0 +1,38 @@
+//! Cymo: multi-threaded FTP and SFTP uploads.
+//!
+//! `Uploader` runs a whole upload and returns an `UploadReport`, the `cymo` binary is a
+//! thin wrapper around it. Every run has its own state, so many runs can share a process.
+
+mod checksum;
+mod commands;
+mod context;
+mod eudora;
+mod filter;
+mod ftp;
+mod interrupt;
+mod journal;
+#[cfg(test)]
+mod memory;
+mod mirror;
+pub mod options;
+mod progress;
+mod reconnect;
+mod release;
+mod remote;
+mod report;
+mod retry;
+mod segment;
+mod sftp;
+mod skip;
+mod throttle;
+mod timeout;
+mod transport;
+mod uploader;
+mod utils;
+
+pub use commands::{ls, mkdir, rm};
+pub use interrupt::StopHandle;
+pub use release::rollback;
+pub use remote::RemoteEntry;
+pub use report::{Event, Events, UploadReport};
+pub use uploader::Uploader;
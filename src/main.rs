@@ -1,83 +1,104 @@
-use crate::args::Args;
-use crate::eudora::is_hidden;
-use crate::utils::{build_worker_task, create_thread_task};
+use crate::{
+    args::{Command, OutputFormat, SkipPolicy},
+    dashboard::Dashboard,
+};
 use anyhow::{anyhow, Result};
-use clap::Parser;
-use crossbeam_channel::unbounded;
+use cymo::{ls, mkdir, options::Options, rm, rollback, Events, Uploader};
 use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex as StdMutex, OnceLock},
-    thread,
+    io::{self, IsTerminal, Write},
+    sync::Arc,
 };
 
-use walkdir::WalkDir;
-
 mod args;
-mod eudora;
-mod utils;
-
-// Arguments
-static ARG: OnceLock<Args> = OnceLock::new();
-// Used for skip folders
-static PARAM_PATH: OnceLock<PathBuf> = OnceLock::new();
-// Remote path, used for detect remote path
-static REMOTE_PATH: OnceLock<PathBuf> = OnceLock::new();
+mod config;
+mod credentials;
+mod dashboard;
+mod signal;
 
 fn main() -> Result<()> {
-    let args = Args::parse();
-    PARAM_PATH.get_or_init(|| PathBuf::from(&args.local_path));
-    REMOTE_PATH.get_or_init(|| PathBuf::from(&args.remote_path));
-    let args = ARG.get_or_init(|| args);
-    // Found files
-    let mut files = WalkDir::new(&args.local_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| !is_hidden(e))
-        .map(|e| PathBuf::from(e.path()))
-        .filter(|e| e.is_file())
-        .collect::<Vec<_>>();
-    files.sort_by_key(|a| a.components().count());
-    let files_count = files.len();
-
-    // One more thread for send task for others
-    let cpus = args
-        .thread
-        .unwrap_or(thread::available_parallelism()?.get());
-    let cpus = if files_count < cpus {
-        files_count
-    } else {
-        cpus
-    };
+    let mut command = config::parse()?.command;
+    credentials::resolve(command.connection_mut())?;
+    match command {
+        Command::Upload(args) => transfer(args.output.format, args.into()),
+        Command::Download(args) => transfer(args.output.format, args.into()),
+        Command::Sync(mut args) => {
+            if args.release {
+                return Err(anyhow!("sync can not be used with --release, use upload"));
+            }
+            args.delete = true;
+            args.skip_existing.get_or_insert(SkipPolicy::Mtime);
+            transfer(args.output.format, args.into())
+        }
+        Command::Ls(args) => {
+            for entry in ls(&args.connection.into(), &args.path, &plain())? {
+                let kind = if entry.is_dir { "d" } else { "-" };
+                let size = entry.size.map(|size| size.to_string()).unwrap_or_default();
+                let modified = entry
+                    .modified
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!("{} {:>12} {:>19} {}", kind, size, modified, entry.name);
+            }
+            Ok(())
+        }
+        Command::Rm(args) => rm(
+            &args.connection.into(),
+            &args.paths,
+            args.recursive,
+            &plain(),
+        ),
+        Command::Mkdir(args) => mkdir(&args.connection.into(), &args.paths, args.parents, &plain()),
+        Command::Rollback(args) => rollback(
+            &args.connection.into(),
+            &args.remote_path,
+            args.to.as_deref(),
+            &plain(),
+        ),
+    }
+}
 
-    // This channel used by send all files to be upload to child threads
-    let (s, r) = unbounded();
-    thread::spawn(build_worker_task(files, cpus, s));
+/// Upload or download files with multiple threads.
+///
+/// Ctrl-C stops the run after files in progress, the journal of pending and failed
+/// files is written for `--resume-journal`.
+fn transfer(format: OutputFormat, options: Options) -> Result<()> {
+    let dashboard = Arc::new(Dashboard::new(format == OutputFormat::Json));
+    let uploader = Uploader::with_options(options)
+        .on_event({
+            let dashboard = dashboard.clone();
+            move |event| dashboard.handle(event)
+        })
+        .confirm_delete(confirm);
+    signal::listen(uploader.stop_handle(), dashboard);
+    let report = uploader.run()?;
+    if report.interrupted {
+        return Err(anyhow!(
+            "Interrupted, {} file(s) pending, {} file(s) failed",
+            report.pending,
+            report.failed
+        ));
+    }
+    if report.failed > 0 {
+        return Err(anyhow!("{} file(s) failed", report.failed));
+    }
+    Ok(())
+}
 
-    // All threads total uploads count
-    let file_count = Arc::new(StdMutex::new(0_usize));
-    // All threads failed files
-    let failed_files = Arc::new(StdMutex::new(Vec::<PathBuf>::new()));
-    let threads = (1..=cpus)
-        .map(create_thread_task(
-            r,
-            file_count.clone(),
-            failed_files.clone(),
-        ))
-        .collect::<Vec<_>>();
-    threads
-        .into_iter()
-        .try_for_each(|thread| thread.join().map_err(|err| anyhow!("{:?}", err)))?;
+/// Messages of remote commands, printed line by line.
+fn plain() -> Events {
+    let dashboard = Dashboard::new(false);
+    Events::new(move |event| dashboard.handle(event))
+}
 
-    let failed_count = failed_files
-        .lock()
-        .map_err(|err| anyhow!("Main thread read failed list failed {}", err))?
-        .len();
-    let count = file_count
-        .lock()
-        .map_err(|err| anyhow!("Main thread read file count failed {}", err))?;
-    println!(
-        "Total find {} file(s) upload {} file(s), {} file(s) failed",
-        files_count, count, failed_count
-    );
-    Ok(())
+/// Ask user to confirm deletion when running in a terminal.
+fn confirm(count: usize) -> bool {
+    if !io::stdin().is_terminal() {
+        return true;
+    }
+    print!("Delete {} remote entries not in local? [y/N] ", count);
+    let mut answer = String::new();
+    if io::stdout().flush().is_err() || io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
 }
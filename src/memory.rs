@@ -0,0 +1,197 @@
+//! A remote file tree in memory, used by tests in place of a server.
+
+use crate::{options::Connection, remote::RemoteEntry, transport::Transport};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Paths are absolute, a directory has no content.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    pub entries: BTreeMap<PathBuf, Option<Vec<u8>>>,
+    cwd: PathBuf,
+}
+
+impl MemoryTransport {
+    /// A tree with only the root directory, files and directories are created
+    /// with parents.
+    pub fn new(files: &[&str], dirs: &[&str]) -> Self {
+        let mut transport = Self {
+            entries: BTreeMap::from([(PathBuf::from("/"), None)]),
+            cwd: PathBuf::from("/"),
+        };
+        for dir in dirs {
+            transport.add_parents(Path::new(dir));
+            transport.entries.insert(PathBuf::from(dir), None);
+        }
+        for file in files {
+            transport.add_parents(Path::new(file));
+            transport
+                .entries
+                .insert(PathBuf::from(file), Some(file.as_bytes().to_vec()));
+        }
+        transport
+    }
+
+    fn add_parents(&mut self, path: &Path) {
+        for parent in path.ancestors().skip(1) {
+            self.entries.insert(parent.to_path_buf(), None);
+        }
+    }
+
+    /// Absolute path of `path` relative to the current directory.
+    fn resolve(&self, path: &str) -> PathBuf {
+        let mut resolved = PathBuf::from("/");
+        for component in self.cwd.join(path).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => {}
+            }
+        }
+        resolved
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(None))
+    }
+
+    fn children(&self, dir: &Path) -> impl Iterator<Item = (&PathBuf, &Option<Vec<u8>>)> {
+        let dir = dir.to_path_buf();
+        self.entries
+            .iter()
+            .filter(move |(path, _)| path.parent() == Some(dir.as_path()))
+    }
+
+    fn file(&self, path: &str) -> Result<&Vec<u8>> {
+        match self.entries.get(&self.resolve(path)) {
+            Some(Some(content)) => Ok(content),
+            _ => Err(anyhow!("550 {}: no such file", path)),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn login(&mut self, _connection: &Connection) -> Result<()> {
+        Ok(())
+    }
+
+    async fn cwd(&mut self, path: &str) -> Result<()> {
+        let path = self.resolve(path);
+        if !self.is_dir(&path) {
+            return Err(anyhow!("550 {:?}: no such directory", path));
+        }
+        self.cwd = path;
+        Ok(())
+    }
+
+    async fn pwd(&mut self) -> Result<String> {
+        Ok(self.cwd.to_string_lossy().to_string())
+    }
+
+    async fn mkdir(&mut self, path: &str) -> Result<()> {
+        let path = self.resolve(path);
+        if self.entries.contains_key(&path) || !path.parent().is_some_and(|p| self.is_dir(p)) {
+            return Err(anyhow!("550 {:?}: can not create", path));
+        }
+        self.entries.insert(path, None);
+        Ok(())
+    }
+
+    async fn rm(&mut self, path: &str) -> Result<()> {
+        self.file(path)?;
+        self.entries.remove(&self.resolve(path));
+        Ok(())
+    }
+
+    async fn rmdir(&mut self, path: &str) -> Result<()> {
+        let path = self.resolve(path);
+        if !self.is_dir(&path) || self.children(&path).next().is_some() {
+            return Err(anyhow!("550 {:?}: not an empty directory", path));
+        }
+        self.entries.remove(&path);
+        Ok(())
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let (from, to) = (self.resolve(from), self.resolve(to));
+        if !self.entries.contains_key(&from) || self.entries.contains_key(&to) {
+            return Err(anyhow!("550 rename {:?} to {:?} refused", from, to));
+        }
+        let moved = self
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(&from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved {
+            let content = self.entries.remove(&path).unwrap();
+            let renamed = to.join(path.strip_prefix(&from).unwrap());
+            self.entries.insert(renamed, content);
+        }
+        Ok(())
+    }
+
+    async fn size(&mut self, path: &str) -> Result<u64> {
+        Ok(self.file(path)?.len() as u64)
+    }
+
+    async fn mdtm(&mut self, path: &str) -> Result<DateTime<Utc>> {
+        Err(anyhow!("502 MDTM {} not implemented", path))
+    }
+
+    async fn list_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>> {
+        let dir = self.resolve(path);
+        if !self.is_dir(&dir) {
+            return Err(anyhow!("550 {:?}: no such directory", dir));
+        }
+        Ok(self
+            .children(&dir)
+            .map(|(path, content)| RemoteEntry {
+                name: path.file_name().unwrap().to_string_lossy().to_string(),
+                is_dir: content.is_none(),
+                size: content.as_ref().map(|content| content.len() as u64),
+                modified: None,
+            })
+            .collect())
+    }
+
+    async fn put(
+        &mut self,
+        path: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        offset: u64,
+    ) -> Result<u64> {
+        let path = self.resolve(path);
+        let mut content = match self.entries.get(&path) {
+            Some(Some(content)) => content[..offset as usize].to_vec(),
+            _ => vec![],
+        };
+        let written = reader.read_to_end(&mut content).await?;
+        self.entries.insert(path, Some(content));
+        Ok(written as u64)
+    }
+
+    async fn get(
+        &mut self,
+        path: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<u64> {
+        let content = self.file(path)?.clone();
+        writer.write_all(&content).await?;
+        Ok(content.len() as u64)
+    }
+
+    async fn quit(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
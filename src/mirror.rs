@@ -0,0 +1,197 @@
+use crate::{
+    context::Context, eudora::is_partial, filter::Filter, options::Options,
+    reconnect::connect_session, transport::Transport,
+};
+
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Remove remote files and directories not exist in local path.
+///
+/// Run after all uploads finished. Entries excluded by `Filter` are ignored on both
+/// sides, they are never uploaded so never deleted.
+///
+/// - With `--dry-run` only print what will be deleted.
+/// - Refuse to delete more than `--max-delete` entries without `--force`.
+/// - Ask `Context::confirm` without `--force`, like a prompt in a terminal.
+///
+/// ## Return
+///
+/// Deleted entries count.
+pub async fn mirror(ctx: &Context) -> Result<usize> {
+    let Options {
+        local_path,
+        remote_path,
+        dry_run,
+        max_delete,
+        force,
+        ..
+    } = &ctx.options;
+    let local_path = PathBuf::from(local_path);
+    if !local_path.is_dir() {
+        return Err(anyhow!("--delete needs --local-path to be a directory"));
+    }
+    let filter = Filter::new(&ctx.options, &ctx.events)?;
+    // All local entries, relative to the local path
+    let local = filter
+        .walk()
+        .filter(|e| e.depth() > 0)
+        .filter_map(|e| e.path().strip_prefix(&local_path).ok().map(PathBuf::from))
+        .collect::<HashSet<_>>();
+
+    let mut ftp_stream = connect_session(ctx, 0, &mut 0).await?;
+
+    let remote_root = PathBuf::from(remote_path);
+    let extra = find_extra(ftp_stream.as_mut(), &remote_root, &local, |path, is_dir| {
+        filter.is_excluded(&local_path.join(path), is_dir)
+    })
+    .await?;
+    if extra.is_empty() {
+        ctx.info("Remote is identical to local, nothing to delete");
+        return Ok(0);
+    }
+    if *dry_run {
+        for (path, _) in &extra {
+            ctx.info(format!("Would delete {:?}", remote_root.join(path)));
+        }
+        ctx.info(format!(
+            "Dry run, {} remote entries not deleted",
+            extra.len()
+        ));
+        return Ok(0);
+    }
+    if extra.len() > *max_delete && !force {
+        return Err(anyhow!(
+            "Refuse to delete {} remote entries, more than --max-delete {}, use --force to continue",
+            extra.len(),
+            max_delete
+        ));
+    }
+    let confirmed = *force
+        || ctx
+            .confirm
+            .as_ref()
+            .is_none_or(|confirm| confirm(extra.len()));
+    if !confirmed {
+        ctx.info("Canceled, no remote entries deleted");
+        return Ok(0);
+    }
+
+    let mut deleted = 0_usize;
+    // Children are always after their parent, delete from the end.
+    for (path, is_dir) in extra.iter().rev() {
+        let remote = remote_root.join(path);
+        let remote = remote.to_string_lossy();
+        let result = if *is_dir {
+            ftp_stream.rmdir(&remote).await
+        } else {
+            ftp_stream.rm(&remote).await
+        };
+        match result {
+            Ok(_) => {
+                ctx.info(format!("Thread 0 delete {} success", remote));
+                deleted += 1;
+            }
+            Err(err) => ctx.warn(format!("Thread 0 delete {} failed, {}", remote, err)),
+        }
+    }
+    ftp_stream.quit().await?;
+    Ok(deleted)
+}
+
+/// Walk the remote tree and collect entries not in local.
+///
+/// Directories not in local are walked too, everything inside must be deleted
+/// before the directory itself, including excluded ones.
+///
+/// `is_excluded` receives the path relative to the remote root.
+///
+/// ## Return
+///
+/// Paths relative to the remote root and whether it is a directory, a parent
+/// is always before its children.
+async fn find_extra(
+    ftp_stream: &mut dyn Transport,
+    remote_root: &Path,
+    local: &HashSet<PathBuf>,
+    is_excluded: impl Fn(&Path, bool) -> bool,
+) -> Result<Vec<(PathBuf, bool)>> {
+    let mut extra = vec![];
+    // Directory relative path and whether it is going to be deleted
+    let mut dirs = vec![(PathBuf::new(), false)];
+    while let Some((dir, deleting)) = dirs.pop() {
+        let entries = ftp_stream
+            .list_dir(&remote_root.join(&dir).to_string_lossy())
+            .await?;
+        for entry in entries {
+            let path = dir.join(&entry.name);
+            if !deleting {
+                // Excluded entries are never uploaded, leave them untouched.
+                // Temporary files left by a broken `--atomic` upload are removed.
+                if is_excluded(&path, entry.is_dir) && !is_partial(&entry.name) {
+                    continue;
+                }
+                if local.contains(&path) {
+                    if entry.is_dir {
+                        dirs.push((path, false));
+                    }
+                    continue;
+                }
+            }
+            if entry.is_dir {
+                dirs.push((path.clone(), true));
+            }
+            extra.push((path, entry.is_dir));
+        }
+    }
+    Ok(extra)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryTransport;
+
+    #[tokio::test]
+    async fn extra_entries() {
+        let mut ftp_stream = MemoryTransport::new(
+            &[
+                "/www/index.html",
+                "/www/old.html",
+                "/www/.env",
+                "/www/.index.html.cymo-partial",
+                "/www/a/keep.js",
+                "/www/a/old.js",
+                "/www/gone/x.js",
+                "/www/gone/.hidden",
+            ],
+            &[],
+        );
+        let local = ["index.html", "a", "a/keep.js"]
+            .map(PathBuf::from)
+            .into_iter()
+            .collect();
+        let is_excluded = |path: &Path, _| path.to_string_lossy().starts_with('.');
+        let mut extra = find_extra(&mut ftp_stream, Path::new("/www"), &local, is_excluded)
+            .await
+            .unwrap();
+        // Parents are before children, the rest of the order is the walk order.
+        let position = |path: &str| extra.iter().position(|(p, _)| p == Path::new(path));
+        assert!(position("gone") < position("gone/x.js"));
+        assert!(position("gone") < position("gone/.hidden"));
+        extra.sort();
+        let expected = [
+            (".index.html.cymo-partial", false),
+            ("a/old.js", false),
+            ("gone", true),
+            ("gone/.hidden", false),
+            ("gone/x.js", false),
+            ("old.html", false),
+        ]
+        .map(|(path, is_dir)| (PathBuf::from(path), is_dir));
+        assert_eq!(extra, expected);
+    }
+}
@@ -0,0 +1,369 @@
+//! Options of a run, the `cymo` binary fills them from its flags.
+
+use chrono::NaiveTime;
+use std::path::PathBuf;
+
+/// Options to connect to the server.
+///
+/// `Default` has the defaults of the `cymo` flags, only `server` has to be set.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    /// Server hostname, may have a scheme and port, like `sftp://example.com:2222`.
+    pub server: String,
+    /// Port over the one of `server` and the default of the protocol.
+    pub port: Option<u32>,
+    /// Protocol over the scheme of `server`.
+    pub protocol: Option<Protocol>,
+    pub username: Option<String>,
+    /// Password, or the passphrase of `identity` for SFTP.
+    pub password: Option<String>,
+    /// Private key for SFTP.
+    pub identity: Option<PathBuf>,
+    pub tls: TlsMode,
+    /// PEM file with CA certificates used to verify the server.
+    pub ca_file: Option<PathBuf>,
+    /// Accept invalid server certificates or unknown SSH host keys.
+    pub insecure: bool,
+    /// Connections transferring at the same time, the CPU count when `None`.
+    pub thread: Option<usize>,
+    /// Attempts of a failed file after the first one, failed files are not retried when `None`.
+    pub retry: Option<u32>,
+    /// Seconds to wait before the first retry or reconnection.
+    pub retry_delay: f64,
+    /// Factor the wait grows by after each failed attempt.
+    pub retry_multiplier: f64,
+    /// Longest wait between attempts in seconds.
+    pub retry_max_delay: f64,
+    /// Part of each wait that is random, from 0 to 1.
+    pub retry_jitter: f64,
+    /// Attempts to connect again when connecting failed or the connection was lost.
+    pub reconnect: u32,
+    /// Seconds to wait for connecting, 0 waits forever.
+    pub connect_timeout: u64,
+    /// Seconds to wait for the reply of a command, 0 waits forever.
+    pub command_timeout: u64,
+    /// Seconds without data flowing before a transfer is stalled, 0 waits forever.
+    pub idle_timeout: u64,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Connection {
+            server: String::new(),
+            port: None,
+            protocol: None,
+            username: None,
+            password: None,
+            identity: None,
+            tls: TlsMode::None,
+            ca_file: None,
+            insecure: false,
+            thread: None,
+            retry: None,
+            retry_delay: 1.0,
+            retry_multiplier: 2.0,
+            retry_max_delay: 30.0,
+            retry_jitter: 0.5,
+            reconnect: 5,
+            connect_timeout: 30,
+            command_timeout: 60,
+            idle_timeout: 60,
+        }
+    }
+}
+
+impl Connection {
+    /// Protocol from `protocol`, or the scheme of `server`, FTP by default.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+            .unwrap_or(match self.server.split_once("://") {
+                Some((scheme, _)) if scheme.eq_ignore_ascii_case("sftp") => Protocol::Sftp,
+                _ => Protocol::Ftp,
+            })
+    }
+
+    /// Server hostname, without scheme and port.
+    pub fn host(&self) -> &str {
+        self.split_server().0
+    }
+
+    /// Server port, from `port`, `server`, or the default of protocol and TLS mode.
+    pub fn port(&self) -> u32 {
+        self.port
+            .or(self.split_server().1)
+            .unwrap_or(match (self.protocol(), self.tls) {
+                (Protocol::Sftp, _) => 22,
+                (Protocol::Ftp, TlsMode::Implicit) => 990,
+                (Protocol::Ftp, _) => 21,
+            })
+    }
+
+    /// Server address with port, a bare IPv6 host is put in brackets.
+    pub fn addr(&self) -> String {
+        let host = self.host();
+        if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]:{}", host, self.port())
+        } else {
+            format!("{}:{}", host, self.port())
+        }
+    }
+
+    /// Split `server` like `sftp://example.com:2222/` into host and port.
+    fn split_server(&self) -> (&str, Option<u32>) {
+        let server = self
+            .server
+            .split_once("://")
+            .map_or(self.server.as_str(), |(_, rest)| rest)
+            .trim_end_matches('/');
+        // A bare IPv6 address like `::1` has colons without a port, it needs brackets.
+        match server.rsplit_once(':') {
+            Some((host, port))
+                if (host.ends_with(']') || !host.contains(':')) && port.parse::<u32>().is_ok() =>
+            {
+                (host, port.parse().ok())
+            }
+            _ => (server, None),
+        }
+    }
+}
+
+/// Options of a transfer, `upload`, `sync` and `download` of the `cymo` binary.
+///
+/// `Default` has the defaults of `cymo upload`, except that the journal and failed
+/// list are not written.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Where files are put, or downloaded from with `download`.
+    pub remote_path: String,
+    /// Directory or file to upload, or the directory downloaded into.
+    pub local_path: String,
+    pub connection: Connection,
+    /// Write the run result as JSON into the file.
+    pub report: Option<PathBuf>,
+    /// Bytes per second of all connections together.
+    pub limit_rate: Option<u64>,
+    /// Bytes per second of each connection.
+    pub limit_per_connection: Option<u64>,
+    /// Rates of daily windows, the first one containing the time wins over `limit_rate`.
+    pub limit_schedule: Vec<RateWindow>,
+    /// Where pending and failed files are written when stopped.
+    pub journal: Option<PathBuf>,
+    /// Only transfer the pending and failed files of this journal.
+    pub resume_journal: Option<PathBuf>,
+    /// Where failed files are written, removed when no file failed.
+    pub failed_list: Option<PathBuf>,
+    /// Only transfer files listed one per line, `-` reads stdin.
+    pub files_from: Option<PathBuf>,
+    /// Continue partially uploaded files from the size already on server.
+    pub resume: bool,
+    /// Upload to a temporary name, renamed to the final name when finished.
+    pub atomic: bool,
+    /// Compare each uploaded file with the local one, mismatches are retried.
+    pub verify: bool,
+    /// Upload into a new release beside the remote path, swapped into place at the end.
+    pub release: bool,
+    /// Previous releases kept for `rollback`.
+    pub keep_releases: usize,
+    /// Parts of a large file uploaded at the same time.
+    pub segments: Option<usize>,
+    /// Smallest file uploaded in `segments`.
+    pub segment_threshold: u64,
+    pub skip_existing: Option<SkipPolicy>,
+    /// Delete remote entries not in the local path after upload.
+    pub delete: bool,
+    /// Only report what `delete` would remove.
+    pub dry_run: bool,
+    /// Maximum remote entries `delete` removes without `force`.
+    pub max_delete: usize,
+    /// Delete without confirmation and `max_delete` limit.
+    pub force: bool,
+    /// Only upload files matching the globs, gitignore syntax.
+    pub include: Vec<String>,
+    /// Do not upload files or directories matching the globs, gitignore syntax.
+    pub exclude: Vec<String>,
+    /// Upload or download hidden files and directories.
+    pub hidden: bool,
+    /// Download `remote_path` into `local_path` instead of upload.
+    pub download: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            remote_path: String::new(),
+            local_path: String::new(),
+            connection: Connection::default(),
+            report: None,
+            limit_rate: None,
+            limit_per_connection: None,
+            limit_schedule: vec![],
+            journal: None,
+            resume_journal: None,
+            failed_list: None,
+            files_from: None,
+            resume: false,
+            atomic: false,
+            verify: false,
+            release: false,
+            keep_releases: 5,
+            segments: None,
+            segment_threshold: 1 << 30,
+            skip_existing: None,
+            delete: false,
+            dry_run: false,
+            max_delete: 100,
+            force: false,
+            include: vec![],
+            exclude: vec![],
+            hidden: false,
+            download: false,
+        }
+    }
+}
+
+impl Options {
+    /// Transfer direction used in messages.
+    pub fn action(&self) -> &'static str {
+        if self.download {
+            "download"
+        } else {
+            "upload"
+        }
+    }
+}
+
+/// Daily time window of `limit_schedule`.
+#[derive(Clone, Debug)]
+pub struct RateWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Bytes per second, `0` is unlimited.
+    pub rate: u64,
+}
+
+impl RateWindow {
+    /// Whether the time is in the window, windows like 22:00-06:00 wrap over midnight,
+    /// 00:00-00:00 is the whole day.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start == self.end {
+            true
+        } else if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// FTPS mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Plain FTP
+    #[default]
+    None,
+    /// Upgrade with `AUTH TLS` after connected
+    Explicit,
+    /// TLS from the first byte, usually on port 990
+    Implicit,
+}
+
+/// Protocol used to connect to the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// FTP, or FTPS with `tls`
+    Ftp,
+    /// SFTP over SSH
+    Sftp,
+}
+
+/// How to decide a remote file is up to date
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipPolicy {
+    /// Same size
+    Size,
+    /// Same size, and remote file is not older than local
+    Mtime,
+    /// Same size and hash, fall back to mtime when server has no hash command
+    Checksum,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(server: &str) -> Connection {
+        Connection {
+            server: server.to_string(),
+            ..Connection::default()
+        }
+    }
+
+    #[test]
+    fn split_server() {
+        let sftp = connection("sftp://example.com:2222/");
+        assert_eq!(sftp.split_server(), ("example.com", Some(2222)));
+        assert_eq!(sftp.protocol(), Protocol::Sftp);
+        assert_eq!(sftp.addr(), "example.com:2222");
+
+        assert_eq!(
+            connection("ftp.example.com").split_server(),
+            ("ftp.example.com", None)
+        );
+        // Colons of an IPv6 address are not taken as the port.
+        assert_eq!(connection("[::1]").split_server(), ("[::1]", None));
+        assert_eq!(connection("[::1]:21").split_server(), ("[::1]", Some(21)));
+        assert_eq!(connection("::1").split_server(), ("::1", None));
+        assert_eq!(connection("::1").addr(), "[::1]:21");
+        assert_eq!(
+            connection("2001:db8::21").split_server(),
+            ("2001:db8::21", None)
+        );
+    }
+
+    #[test]
+    fn port() {
+        assert_eq!(connection("example.com").port(), 21);
+        assert_eq!(connection("sftp://example.com").port(), 22);
+        let implicit = Connection {
+            tls: TlsMode::Implicit,
+            ..connection("example.com")
+        };
+        assert_eq!(implicit.port(), 990);
+        assert_eq!(connection("example.com:2121").port(), 2121);
+        let port = Connection {
+            port: Some(21),
+            ..connection("example.com:2121")
+        };
+        assert_eq!(port.port(), 21);
+        let sftp = Connection {
+            protocol: Some(Protocol::Sftp),
+            ..connection("example.com")
+        };
+        assert_eq!(sftp.port(), 22);
+    }
+
+    #[test]
+    fn window_contains() {
+        let at = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+        let window = |start, end| RateWindow {
+            start: at(start),
+            end: at(end),
+            rate: 1 << 20,
+        };
+        let day = window(9, 18);
+        assert!(day.contains(at(9)));
+        assert!(day.contains(at(17)));
+        assert!(!day.contains(at(18)));
+        assert!(!day.contains(at(3)));
+
+        let night = window(22, 6);
+        assert!(night.contains(at(23)));
+        assert!(night.contains(at(0)));
+        assert!(!night.contains(at(6)));
+        assert!(!night.contains(at(12)));
+
+        let all = window(0, 0);
+        assert!(all.contains(at(0)));
+        assert!(all.contains(at(12)));
+    }
+}
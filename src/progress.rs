@@ -0,0 +1,179 @@
+use crate::{context::Context, report::Event};
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context as TaskContext, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+impl Context {
+    /// Start an attempt of a file on worker `i`, `size` is the bytes to be transferred.
+    pub fn start(&self, i: usize, path: &Path, size: u64) -> FileProgress<'_> {
+        self.emit(Event::FileStarted {
+            thread: i,
+            path: path.to_path_buf(),
+            size,
+        });
+        FileProgress {
+            ctx: self,
+            thread: i,
+            path: path.to_path_buf(),
+            started: Instant::now(),
+            counted: AtomicU64::new(0),
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Remove a file not going to be transferred from totals.
+    pub fn skip(&self, size: u64) {
+        self.emit(Event::FileSkipped { size });
+    }
+
+    /// A file failed, it will not be retried any more.
+    ///
+    /// `duration` includes all retries.
+    pub fn fail(&self, i: usize, path: &Path, err: &anyhow::Error, duration: Duration) {
+        self.emit(Event::FileFailed {
+            thread: i,
+            path: path.to_path_buf(),
+            error: err.to_string(),
+            duration_ms: duration.as_millis(),
+        });
+    }
+
+    /// Bytes actually sent or received.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress of a single attempt of a file, bytes are counted by `reader`.
+///
+/// Many readers can count at the same time, like segments of a file uploaded over
+/// many connections. Dropped without `finish` means the attempt failed, bytes it
+/// counted will be transferred again.
+pub struct FileProgress<'a> {
+    ctx: &'a Context,
+    thread: usize,
+    path: PathBuf,
+    started: Instant,
+    /// Bytes counted on the total, `offset` included.
+    counted: AtomicU64,
+    offset: u64,
+    finished: bool,
+}
+
+impl FileProgress<'_> {
+    /// Bytes already on the other side, like the offset of a resumed upload.
+    pub fn skip_to(&mut self, offset: u64) {
+        self.offset = offset;
+        self.inc(offset);
+    }
+
+    /// Wrap a reader, bytes read are counted on this file and the total.
+    pub fn reader<R: AsyncRead + Unpin>(&self, inner: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            inner,
+            progress: self,
+        }
+    }
+
+    /// Wrap a writer, bytes written are counted on this file and the total.
+    pub fn writer<W: AsyncWrite + Unpin>(&self, inner: W) -> ProgressWriter<'_, W> {
+        ProgressWriter {
+            inner,
+            progress: self,
+        }
+    }
+
+    /// The file is transferred.
+    pub fn finish(mut self) {
+        self.finished = true;
+        self.ctx.emit(Event::FileFinished {
+            thread: self.thread,
+            path: self.path.clone(),
+            bytes: self.counted.load(Ordering::Relaxed) - self.offset,
+            duration_ms: self.started.elapsed().as_millis(),
+        });
+    }
+
+    fn inc(&self, bytes: u64) {
+        self.counted.fetch_add(bytes, Ordering::Relaxed);
+        self.ctx.emit(Event::FileProgress {
+            thread: self.thread,
+            bytes,
+        });
+    }
+
+    /// Count bytes sent or received.
+    fn transfer(&self, bytes: u64) {
+        self.inc(bytes);
+        self.ctx.sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for FileProgress<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.ctx.emit(Event::FileRetried {
+                thread: self.thread,
+                bytes: self.counted.load(Ordering::Relaxed),
+            });
+        }
+    }
+}
+
+/// Reader counting bytes for a `FileProgress`.
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a FileProgress<'a>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            self.progress.transfer(read);
+        }
+        poll
+    }
+}
+
+/// Writer counting bytes for a `FileProgress`.
+pub struct ProgressWriter<'a, W> {
+    inner: W,
+    progress: &'a FileProgress<'a>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ProgressWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.progress.transfer(written as u64);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{
+    context::Context,
+    eudora::connect_and_init,
+    retry::{backoff, is_permanent},
+    transport::{connect, Transport},
+};
+
+use anyhow::Result;
+use std::{io, sync::atomic::Ordering};
+use suppaftp::{FtpError, Status};
+use tokio::time::sleep;
+
+/// Connect, login and change into `--remote-path`.
+///
+/// Failed attempts are tried again up to `--reconnect` times, waiting with the backoff of
+/// `--retry-delay`. Permanent errors like `530` or a rejected host key fail at once.
+///
+/// `reconnects` is the count of the budget already used and grows with every failed
+/// attempt, so a caller reconnecting many times never has more than `--reconnect` tries.
+pub async fn connect_session(
+    ctx: &Context,
+    i: usize,
+    reconnects: &mut u32,
+) -> Result<Box<dyn Transport>> {
+    let connection = &ctx.options.connection;
+    loop {
+        ctx.attempts.fetch_add(1, Ordering::Relaxed);
+        let mut ftp_stream = connect(connection).await;
+        let result = connect_and_init(ctx, ftp_stream.as_mut(), i).await;
+        let err = match (result, ftp_stream) {
+            (Ok(_), Ok(ftp_stream)) => return Ok(ftp_stream),
+            // The connect error itself, `connect_and_init` only has its message.
+            (_, Err(err)) | (Err(err), _) => err,
+        };
+        if *reconnects >= connection.reconnect || is_permanent(&err) {
+            return Err(err);
+        }
+        let delay = backoff(connection, *reconnects);
+        *reconnects += 1;
+        ctx.warn(format!(
+            "Thread {} connect failed {}, try again in {:.1}s",
+            i,
+            err,
+            delay.as_secs_f64()
+        ));
+        sleep(delay).await;
+    }
+}
+
+/// Connect again after the connection was lost, the loss uses up one of `reconnects`.
+pub async fn reconnect_session(
+    ctx: &Context,
+    i: usize,
+    err: &anyhow::Error,
+    reconnects: &mut u32,
+) -> Result<Box<dyn Transport>> {
+    ctx.warn(format!(
+        "Thread {} connection lost {}, reconnecting",
+        i, err
+    ));
+    ctx.reconnects.fetch_add(1, Ordering::Relaxed);
+    *reconnects += 1;
+    connect_session(ctx, i, reconnects).await
+}
+
+/// Whether the error means the control or data connection is broken.
+///
+/// Like `421` replies, EOF, reset, and closed SSH sessions. The connection can not be
+/// used any more, other errors like `550` leave it usable. SFTP file reads and writes
+/// only keep the message of errors, so the SSH connection is checked too.
+pub fn is_disconnected(err: &anyhow::Error, ftp_stream: &dyn Transport) -> bool {
+    ftp_stream.is_closed()
+        || err.chain().any(|cause| {
+            if let Some(err) = cause.downcast_ref::<FtpError>() {
+                return match err {
+                    FtpError::ConnectionError(_) | FtpError::BadResponse => true,
+                    FtpError::UnexpectedResponse(response) => {
+                        response.status == Status::NotAvailable
+                    }
+                    _ => false,
+                };
+            }
+            if let Some(err) = cause.downcast_ref::<russh_sftp::client::error::Error>() {
+                use russh_sftp::client::error::Error as SftpError;
+                return matches!(
+                    err,
+                    SftpError::IO(_) | SftpError::Timeout | SftpError::UnexpectedBehavior(_)
+                );
+            }
+            if cause.downcast_ref::<russh::Error>().is_some() {
+                return true;
+            }
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| is_network(err.kind()))
+        })
+}
+
+/// Whether an I/O error is from a broken or refused connection.
+pub fn is_network(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+    )
+}
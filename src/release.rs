@@ -0,0 +1,302 @@
+use crate::{
+    commands::{mkdir_parents, remote_task, remove_tree, run_remote},
+    options::Connection,
+    report::Events,
+    transport::Transport,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+
+/// File in the releases directory with the name of the live release.
+const CURRENT_FILE: &str = "current";
+/// Suffix of a release being uploaded, it is never rolled back to.
+const PARTIAL_SUFFIX: &str = ".partial";
+/// Release names are the UTC time they were created, so they sort by age.
+const NAME_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// Releases of `--release`, uploaded into `<remote>.releases/<time>.partial`.
+///
+/// When every file succeeded the live directory is moved into the releases directory
+/// under its own name, and the new release is renamed into its place. Nothing is
+/// changed on the live directory when any file failed.
+pub struct Release {
+    /// The live remote path.
+    remote: String,
+    releases: String,
+    name: String,
+    events: Events,
+}
+
+impl Release {
+    /// Create the directory of a new release.
+    pub async fn create(connection: &Connection, remote: &str, events: &Events) -> Result<Self> {
+        let remote = remote.trim_end_matches('/').to_string();
+        let release = Release {
+            releases: format!("{}.releases", remote),
+            remote,
+            name: Utc::now().format(NAME_FORMAT).to_string(),
+            events: events.clone(),
+        };
+        let upload_path = release.upload_path();
+        remote_task(connection, events, |mut ftp_stream| async move {
+            mkdir_parents(ftp_stream.as_mut(), &upload_path, events).await?;
+            Ok(ftp_stream)
+        })
+        .await?;
+        Ok(release)
+    }
+
+    /// Where files of this release are uploaded.
+    pub fn upload_path(&self) -> String {
+        format!("{}/{}{}", self.releases, self.name, PARTIAL_SUFFIX)
+    }
+
+    /// Swap the release into place, then remove releases older than the latest `keep` ones.
+    pub async fn publish(&self, connection: &Connection, keep: usize) -> Result<()> {
+        let events = &self.events;
+        remote_task(connection, events, |mut ftp_stream| async move {
+            let live = current(ftp_stream.as_mut(), &self.releases).await?;
+            swap(
+                ftp_stream.as_mut(),
+                &self.remote,
+                &self.releases,
+                live.as_deref(),
+                &self.upload_path(),
+                events,
+            )
+            .await?;
+            set_current(ftp_stream.as_mut(), &self.releases, &self.name).await?;
+            events.info(format!("Release {} is live at {}", self.name, self.remote));
+            prune(ftp_stream.as_mut(), &self.releases, keep, events).await?;
+            Ok(ftp_stream)
+        })
+        .await
+    }
+}
+
+/// Put a previous release back in place, the live one is kept for another rollback.
+///
+/// `to` is the name of the release, the one before the live release when `None`.
+pub fn rollback(
+    connection: &Connection,
+    remote_path: &str,
+    to: Option<&str>,
+    events: &Events,
+) -> Result<()> {
+    let remote = remote_path.trim_end_matches('/');
+    let releases = format!("{}.releases", remote);
+    run_remote(connection, events, |mut ftp_stream| async move {
+        let live = current(ftp_stream.as_mut(), &releases)
+            .await?
+            .ok_or(anyhow!("No release deployed to {} with --release", remote))?;
+        let names = list_releases(ftp_stream.as_mut(), &releases).await?;
+        let target = match to {
+            Some(to) if names.iter().any(|name| name == to) => to.to_string(),
+            Some(to) => return Err(anyhow!("Release {} not found in {}", to, releases)),
+            None => names.into_iter().rfind(|name| name < &live).ok_or(anyhow!(
+                "No release older than {} in {}",
+                live,
+                releases
+            ))?,
+        };
+        swap(
+            ftp_stream.as_mut(),
+            remote,
+            &releases,
+            Some(&live),
+            &format!("{}/{}", releases, target),
+            events,
+        )
+        .await?;
+        set_current(ftp_stream.as_mut(), &releases, &target).await?;
+        events.info(format!(
+            "Rolled back {} from {} to {}",
+            remote, live, target
+        ));
+        Ok(ftp_stream)
+    })
+}
+
+/// Move the live directory into `releases` as `live`, then rename `next` to the live path.
+///
+/// The live directory is moved back when the second rename failed. A live directory
+/// not deployed by `--release` is kept under a name just before the current time.
+async fn swap(
+    ftp_stream: &mut dyn Transport,
+    remote: &str,
+    releases: &str,
+    live: Option<&str>,
+    next: &str,
+    events: &Events,
+) -> Result<()> {
+    let home = ftp_stream.pwd().await?;
+    let exists = ftp_stream.cwd(remote).await.is_ok();
+    // Leave the live directory, some servers refuse to rename the current directory.
+    // Relative paths are resolved from where we started.
+    ftp_stream.cwd(&home).await?;
+    let retired = if exists {
+        let name = live.map(str::to_string).unwrap_or_else(|| {
+            (Utc::now() - Duration::seconds(1))
+                .format(NAME_FORMAT)
+                .to_string()
+        });
+        let retired = format!("{}/{}", releases, name);
+        ftp_stream
+            .rename(remote, &retired)
+            .await
+            .map_err(|err| anyhow!("Move {} to {} failed {}", remote, retired, err))?;
+        Some(retired)
+    } else {
+        None
+    };
+    if let Err(err) = ftp_stream.rename(next, remote).await {
+        if let Some(retired) = retired {
+            if let Err(err) = ftp_stream.rename(&retired, remote).await {
+                events.warn(format!("Move {} back failed {}", retired, err));
+            }
+        }
+        return Err(anyhow!("Move {} to {} failed {}", next, remote, err));
+    }
+    Ok(())
+}
+
+/// Name of the live release, `None` before the first `--release`.
+async fn current(ftp_stream: &mut dyn Transport, releases: &str) -> Result<Option<String>> {
+    ftp_stream.set_binary(true).await?;
+    let mut name = vec![];
+    match ftp_stream
+        .get(&format!("{}/{}", releases, CURRENT_FILE), &mut name)
+        .await
+    {
+        Ok(_) => Ok(Some(String::from_utf8(name)?.trim().to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+async fn set_current(ftp_stream: &mut dyn Transport, releases: &str, name: &str) -> Result<()> {
+    ftp_stream.set_binary(true).await?;
+    ftp_stream
+        .put(
+            &format!("{}/{}", releases, CURRENT_FILE),
+            &mut name.as_bytes(),
+            0,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Complete releases in the releases directory, oldest first.
+async fn list_releases(ftp_stream: &mut dyn Transport, releases: &str) -> Result<Vec<String>> {
+    let mut names = ftp_stream
+        .list_dir(releases)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.is_dir && !entry.name.ends_with(PARTIAL_SUFFIX))
+        .map(|entry| entry.name)
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Remove releases except the latest `keep` ones, and uploads never published.
+async fn prune(
+    ftp_stream: &mut dyn Transport,
+    releases: &str,
+    keep: usize,
+    events: &Events,
+) -> Result<()> {
+    let mut expired = ftp_stream
+        .list_dir(releases)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.is_dir && entry.name.ends_with(PARTIAL_SUFFIX))
+        .map(|entry| entry.name)
+        .collect::<Vec<_>>();
+    let names = list_releases(ftp_stream, releases).await?;
+    expired.extend(names.iter().take(names.len().saturating_sub(keep)).cloned());
+    for name in expired {
+        let path = format!("{}/{}", releases, name);
+        if let Err(err) = remove_tree(ftp_stream, &path, events).await {
+            events.warn(format!("Remove old release {} failed {}", path, err));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryTransport;
+    use std::path::Path;
+
+    /// Upload a release with one file and publish it like `Release` does.
+    async fn deploy(ftp_stream: &mut MemoryTransport, name: &str, file: &str) {
+        let events = Events::default();
+        let upload_path = format!("site.releases/{}{}", name, PARTIAL_SUFFIX);
+        mkdir_parents(ftp_stream, &upload_path, &events)
+            .await
+            .unwrap();
+        let path = format!("{}/{}", upload_path, file);
+        ftp_stream.put(&path, &mut &b"x"[..], 0).await.unwrap();
+        let live = current(ftp_stream, "site.releases").await.unwrap();
+        swap(
+            ftp_stream,
+            "site",
+            "site.releases",
+            live.as_deref(),
+            &upload_path,
+            &events,
+        )
+        .await
+        .unwrap();
+        set_current(ftp_stream, "site.releases", name)
+            .await
+            .unwrap();
+        prune(ftp_stream, "site.releases", 1, &events)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn release_relative_remote_path() {
+        let mut ftp_stream = MemoryTransport::new(&[], &["/home"]);
+        ftp_stream.cwd("/home").await.unwrap();
+        deploy(&mut ftp_stream, "20240101000000", "a.html").await;
+        deploy(&mut ftp_stream, "20240102000000", "b.html").await;
+        deploy(&mut ftp_stream, "20240103000000", "c.html").await;
+
+        let entries = &ftp_stream.entries;
+        assert!(entries.contains_key(Path::new("/home/site/c.html")));
+        // The latest previous release is kept, older ones are pruned.
+        assert!(entries.contains_key(Path::new("/home/site.releases/20240102000000/b.html")));
+        assert!(!entries.contains_key(Path::new("/home/site.releases/20240101000000")));
+        assert!(!entries
+            .keys()
+            .any(|path| path.ends_with("site.releases/site.releases")));
+    }
+
+    #[tokio::test]
+    async fn prune_release_with_hidden_files() {
+        let mut ftp_stream = MemoryTransport::new(
+            &[
+                "/site.releases/20240101000000/.htaccess",
+                "/site.releases/20240101000000/.a.html.cymo-partial",
+                "/site.releases/20240102000000.partial/.htaccess",
+                "/site.releases/20240103000000/index.html",
+            ],
+            &[],
+        );
+        prune(&mut ftp_stream, "/site.releases", 1, &Events::default())
+            .await
+            .unwrap();
+        let names = ftp_stream
+            .list_dir("/site.releases")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["20240103000000"]);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::transport::Transport;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+
+/// A file or directory on the server.
+#[derive(Debug, Clone)]
+pub struct RemoteEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Size in bytes, `None` when the listing does not have it.
+    pub size: Option<u64>,
+    /// Last modified time, `None` when the listing does not have it.
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// Walk a remote directory recursively, hidden entries are skipped unless `hidden`.
+///
+/// Deleting a tree must see hidden entries, a directory is only empty without them.
+///
+/// ## Return
+///
+/// Full remote paths of all files, and all directories with a parent before its children.
+pub async fn list_tree(
+    ftp_stream: &mut dyn Transport,
+    root: &Path,
+    hidden: bool,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = vec![];
+    let mut dirs = vec![];
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in ftp_stream.list_dir(&dir.to_string_lossy()).await? {
+            if !hidden && entry.name.starts_with('.') {
+                continue;
+            }
+            let path = dir.join(&entry.name);
+            if entry.is_dir {
+                pending.push(path.clone());
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok((files, dirs))
+}
+
+/// Fill size and modify time missing from the listing with `SIZE` and `MDTM`.
+///
+/// ## Arguments
+///
+/// - `path`: full remote path of the entry.
+pub async fn stat(ftp_stream: &mut dyn Transport, path: &str, entry: &mut RemoteEntry) {
+    if entry.size.is_none() {
+        entry.size = ftp_stream.size(path).await.ok();
+    }
+    if entry.modified.is_none() {
+        entry.modified = ftp_stream.mdtm(path).await.ok();
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// What happens during a run, sent to the handler of `Uploader::on_event`.
+///
+/// The library prints nothing, messages are `Info` and `Warn` events. `cymo` draws
+/// progress bars from them, or prints them as JSON lines with `--output json`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Status line, like a thread changing directory.
+    Info {
+        message: String,
+    },
+    /// Error line, like a failed attempt going to be retried.
+    Warn {
+        message: String,
+    },
+    /// Files are found and threads are about to start.
+    Started {
+        files: usize,
+        /// `None` when sizes are only known when files start, like downloads.
+        bytes: Option<u64>,
+    },
+    Connected {
+        thread: usize,
+        server: String,
+    },
+    Mkdir {
+        thread: usize,
+        path: String,
+    },
+    /// An attempt of a file started, `size` is the bytes to be transferred.
+    FileStarted {
+        thread: usize,
+        path: PathBuf,
+        size: u64,
+    },
+    /// More bytes of the file in progress on the thread are on the other side.
+    FileProgress {
+        thread: usize,
+        bytes: u64,
+    },
+    /// The attempt failed, `bytes` it counted will be transferred again.
+    FileRetried {
+        thread: usize,
+        bytes: u64,
+    },
+    FileFinished {
+        thread: usize,
+        path: PathBuf,
+        bytes: u64,
+        duration_ms: u128,
+    },
+    /// The file will not be retried any more.
+    FileFailed {
+        thread: usize,
+        path: PathBuf,
+        error: String,
+        duration_ms: u128,
+    },
+    /// A file already up to date on server, removed from the totals of `Started`.
+    FileSkipped {
+        size: u64,
+    },
+    /// All threads finished, only the summary and `--delete` are left.
+    Finished,
+    Summary(UploadReport),
+}
+
+/// Handler of the events of a run, events are dropped without one.
+#[derive(Clone, Default)]
+pub struct Events(Option<Arc<dyn Fn(Event) + Send + Sync>>);
+
+impl Events {
+    pub fn new(handler: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        Events(Some(Arc::new(handler)))
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(handler) = &self.0 {
+            handler(event);
+        }
+    }
+
+    pub fn info(&self, message: impl Display) {
+        self.emit(Event::Info {
+            message: message.to_string(),
+        });
+    }
+
+    pub fn warn(&self, message: impl Display) {
+        self.emit(Event::Warn {
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Result of a whole run, the last event of `--output json` and the content of `--report`.
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadReport {
+    pub action: &'static str,
+    pub server: String,
+    pub local_path: String,
+    pub remote_path: String,
+    /// Files found to be transferred
+    pub found: usize,
+    pub transferred: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub failed_files: Vec<PathBuf>,
+    /// Files not transferred since the run was interrupted
+    pub pending: usize,
+    /// Whether the run was stopped by `StopHandle`, like Ctrl-C
+    pub interrupted: bool,
+    /// Connections tried by all threads, reconnections included
+    pub connection_attempts: usize,
+    /// Connections opened again after one was lost
+    pub reconnects: usize,
+    /// Remote entries removed by `--delete`, `None` without it
+    pub deleted: Option<usize>,
+    pub bytes: u64,
+    pub duration_ms: u128,
+}
+
+impl UploadReport {
+    /// Write into the `--report` file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{options::Connection, reconnect::is_network};
+
+use russh_sftp::{client::error::Error as SftpError, protocol::StatusCode};
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io,
+    time::Duration,
+};
+use suppaftp::FtpError;
+
+/// Wait before attempt `attempt + 1`.
+///
+/// Starts from `--retry-delay`, multiplied by `--retry-multiplier` after each failure
+/// up to `--retry-max-delay`. `--retry-jitter` of it is random, so threads failed by the
+/// same outage do not hit the server at the same moment.
+pub fn backoff(connection: &Connection, attempt: u32) -> Duration {
+    let delay = (connection.retry_delay
+        * connection
+            .retry_multiplier
+            .powi(attempt.min(i32::MAX as u32) as i32))
+    .min(connection.retry_max_delay);
+    let jitter = connection.retry_jitter;
+    Duration::from_secs_f64(delay * (1.0 - jitter + jitter * random()))
+}
+
+/// Random number in `[0, 1)`, the hasher is seeded randomly on every call.
+fn random() -> f64 {
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// An error trying again can not fix, like rejected credentials or an unknown host key.
+#[derive(Debug)]
+pub struct Permanent(pub String);
+
+impl fmt::Display for Permanent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Permanent {}
+
+/// Whether trying again can not help, so the file fails without using up `--retry`.
+///
+/// `5xx` replies, TLS failures, SFTP statuses like permission denied, `Permanent` errors
+/// and local I/O errors like a missing file are permanent. `4xx` replies, network errors,
+/// and anything unknown, like a checksum mismatch, are worth another try.
+pub fn is_permanent(err: &anyhow::Error) -> bool {
+    // The outermost known error decides, wrapped causes only tell how it happened.
+    err.chain()
+        .find_map(|cause| {
+            if cause.is::<Permanent>() {
+                return Some(true);
+            }
+            if let Some(err) = cause.downcast_ref::<FtpError>() {
+                return Some(match err {
+                    FtpError::UnexpectedResponse(response) => response.status.code() >= 500,
+                    FtpError::SecureError(_) => true,
+                    _ => false,
+                });
+            }
+            if let Some(err) = cause.downcast_ref::<SftpError>() {
+                return Some(match err {
+                    SftpError::Status(status) => matches!(
+                        status.status_code,
+                        StatusCode::NoSuchFile
+                            | StatusCode::PermissionDenied
+                            | StatusCode::OpUnsupported
+                    ),
+                    _ => false,
+                });
+            }
+            cause
+                .downcast_ref::<io::Error>()
+                .map(|err| !is_network(err.kind()) && err.kind() != io::ErrorKind::Interrupted)
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use russh_sftp::protocol::Status as SftpStatus;
+    use suppaftp::{types::Response, Status};
+
+    #[test]
+    fn backoff_grows_to_max() {
+        let connection = Connection {
+            retry_delay: 1.0,
+            retry_multiplier: 3.0,
+            retry_max_delay: 10.0,
+            retry_jitter: 0.0,
+            ..Connection::default()
+        };
+        let secs = |attempt| backoff(&connection, attempt).as_secs_f64();
+        assert_eq!(secs(0), 1.0);
+        assert_eq!(secs(1), 3.0);
+        assert_eq!(secs(2), 9.0);
+        assert_eq!(secs(3), 10.0);
+        assert_eq!(secs(u32::MAX), 10.0);
+    }
+
+    #[test]
+    fn backoff_jitter() {
+        let connection = Connection {
+            retry_jitter: 0.5,
+            ..Connection::default()
+        };
+        for _ in 0..100 {
+            let delay = backoff(&connection, 1).as_secs_f64();
+            assert!((1.0..2.0).contains(&delay), "{}", delay);
+        }
+    }
+
+    fn ftp(status: Status) -> anyhow::Error {
+        FtpError::UnexpectedResponse(Response::new(status, vec![])).into()
+    }
+
+    fn sftp(status_code: StatusCode) -> anyhow::Error {
+        SftpError::Status(SftpStatus {
+            id: 0,
+            status_code,
+            error_message: String::new(),
+            language_tag: String::new(),
+        })
+        .into()
+    }
+
+    #[test]
+    fn permanent() {
+        assert!(is_permanent(&ftp(Status::FileUnavailable)));
+        assert!(is_permanent(&ftp(Status::NotLoggedIn)));
+        assert!(is_permanent(&sftp(StatusCode::PermissionDenied)));
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert!(is_permanent(&missing.into()));
+        let host_key = anyhow::Error::new(Permanent("unknown host key".to_string()));
+        assert!(is_permanent(&host_key.context("connect")));
+        let tls = FtpError::SecureError("certificate verify failed".to_string());
+        assert!(is_permanent(&tls.into()));
+        // The outermost known error decides.
+        let wrapped = ftp(Status::FileUnavailable).context("upload a");
+        assert!(is_permanent(&wrapped));
+    }
+
+    #[test]
+    fn temporary() {
+        assert!(!is_permanent(&ftp(Status::NotAvailable)));
+        assert!(!is_permanent(&ftp(Status::TransferAborted)));
+        assert!(!is_permanent(&sftp(StatusCode::Failure)));
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(!is_permanent(&reset.into()));
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert!(!is_permanent(&refused.into()));
+        assert!(!is_permanent(&anyhow!("checksum mismatch")));
+    }
+}
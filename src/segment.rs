@@ -0,0 +1,172 @@
+use crate::{
+    context::Context, progress::FileProgress, reconnect::connect_session, throttle::throttle,
+    transport::Transport,
+};
+
+use anyhow::Result;
+use futures::future::try_join_all;
+use std::{fs::Metadata, io::SeekFrom, ops::Range, path::Path};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+/// Segments to upload a file in, `1` means a normal upload.
+///
+/// Only binary files not smaller than `--segment-threshold` are split into
+/// `--segments`. FTP servers must advertise `REST STREAM` in `FEAT`, since every
+/// segment is written at its offset with `REST` + `STOR`.
+pub async fn segment_count(
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
+    metadata: &Metadata,
+    is_binary: bool,
+) -> usize {
+    let options = &ctx.options;
+    let Some(segments) = options.segments.filter(|segments| *segments > 1) else {
+        return 1;
+    };
+    if !is_binary || metadata.len() < options.segment_threshold.max(2) {
+        return 1;
+    }
+    if let Some(ftp) = ftp_stream.as_ftp() {
+        let rest_stream = ftp.feat().await.is_ok_and(|features| {
+            features.iter().any(|(key, value)| {
+                key.eq_ignore_ascii_case("REST")
+                    && value
+                        .as_deref()
+                        .is_some_and(|value| value.eq_ignore_ascii_case("STREAM"))
+            })
+        });
+        if !rest_stream {
+            ctx.info("Server does not support REST STREAM, upload without segments");
+            return 1;
+        }
+    }
+    segments
+}
+
+/// Upload a file into `target` in `segments` parts at the same time.
+///
+/// The first byte is sent alone, which creates or truncates the remote file. The rest
+/// is split into `segments` ranges, each written at its offset over its own connection,
+/// `ftp_stream` sends the first range and others are connected for the upload.
+///
+/// A failed segment fails the whole file, a retry starts over since the remote file
+/// may have holes.
+pub async fn upload_segments(
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
+    i: usize,
+    path: &Path,
+    target: &str,
+    progress: &FileProgress<'_>,
+    segments: usize,
+) -> Result<()> {
+    let size = path.metadata()?.len();
+    let mut local = File::open(path).await?;
+    ftp_stream
+        .put(target, &mut progress.reader((&mut local).take(1)), 0)
+        .await?;
+
+    let ranges = split(1..size, segments);
+    let remote_dir = ftp_stream.pwd().await?;
+    // Extra connections share one `--reconnect` budget.
+    let mut reconnects = 0;
+    let mut others = vec![];
+    for _ in 1..ranges.len() {
+        match connect_segment(ctx, i, &remote_dir, &mut reconnects).await {
+            Ok(other) => others.push(other),
+            Err(err) => {
+                quit_all(others).await;
+                return Err(err);
+            }
+        }
+    }
+
+    let streams = std::iter::once(ftp_stream).chain(
+        others
+            .iter_mut()
+            .map(|other| -> &mut dyn Transport { other.as_mut() }),
+    );
+    let uploads = streams.zip(ranges).map(|(stream, range)| async move {
+        let mut local = File::open(path).await?;
+        local.seek(SeekFrom::Start(range.start)).await?;
+        let reader = local.take(range.end - range.start);
+        stream
+            .put(
+                target,
+                &mut throttle(ctx, progress.reader(reader)),
+                range.start,
+            )
+            .await?;
+        anyhow::Ok(())
+    });
+    let result = try_join_all(uploads).await;
+    quit_all(others).await;
+    result?;
+    Ok(())
+}
+
+/// Connect one more connection for a segment, in the directory of the first one.
+async fn connect_segment(
+    ctx: &Context,
+    i: usize,
+    remote_dir: &str,
+    reconnects: &mut u32,
+) -> Result<Box<dyn Transport>> {
+    let mut other = connect_session(ctx, i, reconnects).await?;
+    let prepared = async {
+        other.set_binary(true).await?;
+        other.cwd(remote_dir).await
+    }
+    .await;
+    match prepared {
+        Ok(()) => Ok(other),
+        Err(err) => {
+            let _ = other.quit().await;
+            Err(err)
+        }
+    }
+}
+
+/// Close the extra connections, the file already succeeded or failed.
+async fn quit_all(others: Vec<Box<dyn Transport>>) {
+    for mut other in others {
+        let _ = other.quit().await;
+    }
+}
+
+/// Split a byte range into at most `count` ranges of about the same length.
+fn split(range: Range<u64>, count: usize) -> Vec<Range<u64>> {
+    let len = range.end - range.start;
+    let step = len.div_ceil(count as u64).max(1);
+    (range.start..range.end)
+        .step_by(step as usize)
+        .map(|start| start..(start + step).min(range.end))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_even() {
+        assert_eq!(split(0..100, 4), [0..25, 25..50, 50..75, 75..100]);
+        assert_eq!(split(10..20, 2), [10..15, 15..20]);
+    }
+
+    #[test]
+    fn split_uneven() {
+        assert_eq!(split(0..10, 3), [0..4, 4..8, 8..10]);
+        // Fewer ranges than asked, none is empty.
+        assert_eq!(split(0..9, 4), [0..3, 3..6, 6..9]);
+        assert_eq!(split(0..2, 8), [0..1, 1..2]);
+    }
+
+    #[test]
+    fn split_empty() {
+        assert!(split(5..5, 4).is_empty());
+    }
+}
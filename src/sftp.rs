@@ -0,0 +1,249 @@
+use crate::{options::Connection, remote::RemoteEntry, retry::Permanent, transport::Transport};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use russh::{
+    client,
+    keys::{check_known_hosts, load_secret_key, PrivateKeyWithHashAlg, PublicKey},
+    Disconnect,
+};
+use russh_sftp::{
+    client::{fs::Metadata, SftpSession},
+    protocol::OpenFlags,
+};
+use std::{io::SeekFrom, sync::Arc};
+use tokio::io::{self, AsyncRead, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// SFTP connection over SSH.
+///
+/// SFTP has no current directory, `cwd` only remembers it and relative paths
+/// are joined to it.
+pub struct Sftp {
+    handle: client::Handle<Client>,
+    /// Started after login.
+    session: Option<SftpSession>,
+    cwd: String,
+}
+
+/// Verify server key with `~/.ssh/known_hosts`, any key is accepted with `--insecure`.
+pub struct Client {
+    host: String,
+    port: u16,
+    insecure: bool,
+}
+
+impl client::Handler for Client {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(&mut self, server_public_key: &PublicKey) -> Result<bool> {
+        if self.insecure {
+            return Ok(true);
+        }
+        match check_known_hosts(&self.host, self.port, server_public_key) {
+            Ok(true) => Ok(true),
+            Ok(false) => Err(Permanent(format!(
+                "Host key of {} not found in known_hosts, add it with ssh-keyscan or use --insecure",
+                self.host
+            ))
+            .into()),
+            Err(err) => Err(Permanent(format!(
+                "Verify host key of {} failed {}",
+                self.host, err
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Connect to the SSH server, the SFTP session is started by `login`.
+pub async fn connect(connection: &Connection) -> Result<Sftp> {
+    let client = Client {
+        host: connection.host().to_string(),
+        port: connection.port() as u16,
+        insecure: connection.insecure,
+    };
+    let handle = client::connect(
+        Arc::new(client::Config::default()),
+        connection.addr(),
+        client,
+    )
+    .await?;
+    Ok(Sftp {
+        handle,
+        session: None,
+        cwd: String::new(),
+    })
+}
+
+impl Sftp {
+    fn session(&self) -> Result<&SftpSession> {
+        self.session
+            .as_ref()
+            .ok_or(anyhow!("SFTP session not started, login first"))
+    }
+
+    /// Join relative path to the current directory.
+    fn resolve(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.cwd.trim_end_matches('/'), path)
+        }
+    }
+
+    async fn metadata(&self, path: &str) -> Result<Metadata> {
+        Ok(self.session()?.metadata(self.resolve(path)).await?)
+    }
+}
+
+#[async_trait]
+impl Transport for Sftp {
+    /// Try `--identity` first, then `--password`.
+    async fn login(&mut self, connection: &Connection) -> Result<()> {
+        let username = connection
+            .username
+            .as_deref()
+            .ok_or(Permanent("SFTP needs --username".to_string()))?;
+        let mut success = false;
+        if let Some(identity) = &connection.identity {
+            let key = load_secret_key(identity, connection.password.as_deref()).map_err(|err| {
+                Permanent(format!("Read private key {:?} failed {}", identity, err))
+            })?;
+            let hash = self.handle.best_supported_rsa_hash().await?.flatten();
+            success = self
+                .handle
+                .authenticate_publickey(username, PrivateKeyWithHashAlg::new(Arc::new(key), hash))
+                .await?
+                .success();
+        }
+        if let (false, Some(password)) = (success, &connection.password) {
+            success = self
+                .handle
+                .authenticate_password(username, password)
+                .await?
+                .success();
+        }
+        if !success {
+            return Err(Permanent(format!("SFTP authentication of {} failed", username)).into());
+        }
+        let channel = self.handle.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        let session = SftpSession::new(channel.into_stream()).await?;
+        self.cwd = session.canonicalize(".").await?;
+        self.session = Some(session);
+        Ok(())
+    }
+
+    /// Only change to an existing directory, like FTP does.
+    async fn cwd(&mut self, path: &str) -> Result<()> {
+        let session = self.session()?;
+        let path = session.canonicalize(self.resolve(path)).await?;
+        if !session.metadata(&path).await?.is_dir() {
+            return Err(anyhow!("{} is not a directory", path));
+        }
+        self.cwd = path;
+        Ok(())
+    }
+
+    async fn pwd(&mut self) -> Result<String> {
+        Ok(self.cwd.clone())
+    }
+
+    async fn mkdir(&mut self, path: &str) -> Result<()> {
+        Ok(self.session()?.create_dir(self.resolve(path)).await?)
+    }
+
+    async fn rm(&mut self, path: &str) -> Result<()> {
+        Ok(self.session()?.remove_file(self.resolve(path)).await?)
+    }
+
+    async fn rmdir(&mut self, path: &str) -> Result<()> {
+        Ok(self.session()?.remove_dir(self.resolve(path)).await?)
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        Ok(self
+            .session()?
+            .rename(self.resolve(from), self.resolve(to))
+            .await?)
+    }
+
+    async fn size(&mut self, path: &str) -> Result<u64> {
+        self.metadata(path)
+            .await?
+            .size
+            .ok_or(anyhow!("No size of {}", path))
+    }
+
+    async fn mdtm(&mut self, path: &str) -> Result<DateTime<Utc>> {
+        Ok(self.metadata(path).await?.modified()?.into())
+    }
+
+    /// Links are dropped, just like `MLSD`.
+    async fn list_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>> {
+        let entries = self
+            .session()?
+            .read_dir(self.resolve(path))
+            .await?
+            .filter(|entry| entry.file_name() != "." && entry.file_name() != "..")
+            .filter(|entry| entry.file_type().is_dir() || entry.file_type().is_file())
+            .map(|entry| {
+                let metadata = entry.metadata();
+                RemoteEntry {
+                    name: entry.file_name(),
+                    is_dir: entry.file_type().is_dir(),
+                    size: metadata.size,
+                    modified: metadata.modified().ok().map(DateTime::from),
+                }
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    async fn put(
+        &mut self,
+        path: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        offset: u64,
+    ) -> Result<u64> {
+        let flags = if offset > 0 {
+            OpenFlags::CREATE | OpenFlags::WRITE
+        } else {
+            OpenFlags::CREATE | OpenFlags::WRITE | OpenFlags::TRUNCATE
+        };
+        let mut remote = self
+            .session()?
+            .open_with_flags(self.resolve(path), flags)
+            .await?;
+        remote.seek(SeekFrom::Start(offset)).await?;
+        let size = io::copy(reader, &mut remote).await?;
+        remote.shutdown().await?;
+        Ok(size)
+    }
+
+    async fn get(
+        &mut self,
+        path: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<u64> {
+        let mut remote = self.session()?.open(self.resolve(path)).await?;
+        let size = io::copy(&mut remote, writer).await?;
+        remote.shutdown().await?;
+        Ok(size)
+    }
+
+    async fn quit(&mut self) -> Result<()> {
+        if let Some(session) = self.session.take() {
+            session.close().await?;
+        }
+        self.handle
+            .disconnect(Disconnect::ByApplication, "", "en")
+            .await?;
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.handle.is_closed()
+    }
+}
@@ -0,0 +1,51 @@
+use crate::dashboard::Dashboard;
+
+use cymo::StopHandle;
+use std::{io, process, sync::Arc, thread};
+use tokio::{runtime, signal::ctrl_c};
+
+/// Handle Ctrl-C and SIGTERM in a background thread.
+///
+/// The first signal stops sending new files, files in progress finish. The second
+/// aborts them, the third exits at once.
+pub fn listen(handle: StopHandle, dashboard: Arc<Dashboard>) {
+    thread::spawn(move || {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("create tokio runtime failed");
+        rt.block_on(async {
+            let mut signals = 0;
+            while wait_signal().await.is_ok() {
+                signals += 1;
+                match signals {
+                    1 => {
+                        handle.stop();
+                        dashboard.warn("Interrupted, waiting for files in progress, press Ctrl-C again to abort them");
+                    }
+                    2 => {
+                        dashboard.warn("Aborting files in progress");
+                        handle.abort();
+                    }
+                    _ => process::exit(130),
+                }
+            }
+        });
+    });
+}
+
+#[cfg(unix)]
+async fn wait_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_signal() -> io::Result<()> {
+    ctrl_c().await
+}
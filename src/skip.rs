@@ -0,0 +1,115 @@
+use crate::{
+    checksum::{self, Checksum},
+    context::Context,
+    eudora::remote_dir,
+    options::SkipPolicy,
+    remote::{stat, RemoteEntry},
+    transport::Transport,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Drop files already up to date on server.
+///
+/// Files are grouped by their remote directory, each directory is listed only once.
+/// A directory failed to list is treated as empty, all files in it will be uploaded.
+///
+/// ## Arguments
+///
+/// - `files`: total found files path.
+/// - `policy`: how to decide a remote file is up to date.
+///
+/// ## Return
+///
+/// Files need to be uploaded, and the count of skipped files.
+pub async fn filter_up_to_date(
+    ctx: &Context,
+    ftp_stream: &mut dyn Transport,
+    files: Vec<PathBuf>,
+    policy: SkipPolicy,
+) -> Result<(Vec<PathBuf>, usize)> {
+    let checksum = if policy == SkipPolicy::Checksum {
+        let checksum = ctx.checksum();
+        if checksum.is_none() {
+            ctx.warn("Server does not support any hash command, compare modify time instead");
+        }
+        checksum
+    } else {
+        None
+    };
+
+    let mut listed: HashMap<PathBuf, Vec<RemoteEntry>> = HashMap::new();
+    let mut upload_files = vec![];
+    let mut skipped = 0_usize;
+    for file in files {
+        let dir = remote_dir(ctx, file.parent().unwrap_or(Path::new("")));
+        if !listed.contains_key(&dir) {
+            let entries = ftp_stream
+                .list_dir(&dir.to_string_lossy())
+                .await
+                .unwrap_or_default();
+            listed.insert(dir.clone(), entries);
+        }
+        let name = file.file_name().map(|name| name.to_string_lossy());
+        let entry = listed.get_mut(&dir).and_then(|entries| {
+            entries
+                .iter_mut()
+                .find(|entry| !entry.is_dir && Some(entry.name.as_str()) == name.as_deref())
+        });
+        let Some(entry) = entry else {
+            upload_files.push(file);
+            continue;
+        };
+        let remote = dir.join(&entry.name);
+        let remote = remote.to_string_lossy();
+        if up_to_date(ftp_stream, &file, &remote, entry, policy, checksum)
+            .await
+            .unwrap_or(false)
+        {
+            ctx.info(format!("Thread 0 skip {:?}, already up to date", file));
+            ctx.skip(entry.size.unwrap_or(0));
+            skipped += 1;
+        } else {
+            upload_files.push(file);
+        }
+    }
+    Ok((upload_files, skipped))
+}
+
+/// Compare a local file with the remote one.
+///
+/// Sizes must be the same for every policy. The uploaded file gets the upload time
+/// as modify time, so it is up to date when it is not older than the local file.
+async fn up_to_date(
+    ftp_stream: &mut dyn Transport,
+    local: &Path,
+    remote: &str,
+    entry: &mut RemoteEntry,
+    policy: SkipPolicy,
+    checksum: Option<Checksum>,
+) -> Result<bool> {
+    stat(ftp_stream, remote, entry).await;
+    let metadata = tokio::fs::metadata(local).await?;
+    if entry.size != Some(metadata.len()) {
+        return Ok(false);
+    }
+    match (policy, checksum) {
+        (SkipPolicy::Size, _) => Ok(true),
+        (SkipPolicy::Checksum, Some(checksum)) => {
+            let remote_digest = checksum::remote_digest(ftp_stream, checksum, remote).await?;
+            let local_digest = checksum::local_digest(local, checksum.algorithm).await?;
+            Ok(remote_digest == local_digest)
+        }
+        _ => {
+            let local_modified: DateTime<Utc> = metadata.modified()?.into();
+            Ok(entry
+                .modified
+                .is_some_and(|modified| modified.timestamp() >= local_modified.timestamp()))
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{context::Context, options::RateWindow};
+
+use chrono::Local;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Mutex,
+    task::{ready, Context as TaskContext, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Instant, Sleep},
+};
+
+/// Bucket shared by all connections of a run for `--limit-rate` and `--limit-schedule`.
+#[derive(Default)]
+pub struct Limiter(Mutex<Option<Bucket>>);
+
+/// Token bucket allowing one second of burst.
+///
+/// Bytes are taken after they were transferred, the bucket goes into debt and the
+/// transfer waits until it is paid, so the average rate is exact for any chunk size.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Bucket {
+            tokens: 0.0,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take `bytes` at `rate` bytes per second, returns how long to wait for the debt.
+    fn take(&mut self, bytes: u64, rate: u64) -> Duration {
+        let now = Instant::now();
+        let rate = rate as f64;
+        let refill = now.duration_since(self.updated).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(rate) - bytes as f64;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+/// Rate shared by all connections now, `None` is unlimited.
+///
+/// The first window of `--limit-schedule` containing the local time wins over
+/// `--limit-rate`, a rate of `0` is unlimited.
+fn global_rate(limit_rate: Option<u64>, schedule: &[RateWindow]) -> Option<u64> {
+    let now = Local::now().time();
+    schedule
+        .iter()
+        .find(|window| window.contains(now))
+        .map(|window| window.rate)
+        .or(limit_rate)
+        .filter(|rate| *rate > 0)
+}
+
+/// Limit the rate of a reader or writer by `--limit-rate`, `--limit-schedule` and
+/// `--limit-per-connection`.
+///
+/// Each wrapped stream is one connection, nothing is limited without those flags.
+pub fn throttle<T>(ctx: &Context, inner: T) -> Throttled<'_, T> {
+    let options = &ctx.options;
+    let limited = options.limit_rate.is_some()
+        || !options.limit_schedule.is_empty()
+        || options.limit_per_connection.is_some();
+    Throttled {
+        inner,
+        ctx,
+        limited,
+        connection: Bucket::new(),
+        wait: None,
+    }
+}
+
+/// Reader or writer waiting between chunks to keep under the limits.
+pub struct Throttled<'a, T> {
+    inner: T,
+    ctx: &'a Context,
+    limited: bool,
+    connection: Bucket,
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Throttled<'_, T> {
+    /// Wait for the previous chunk to be paid.
+    fn poll_wait(&mut self, cx: &mut TaskContext<'_>) -> Poll<()> {
+        if let Some(wait) = &mut self.wait {
+            ready!(wait.as_mut().poll(cx));
+            self.wait = None;
+        }
+        Poll::Ready(())
+    }
+
+    /// Pay `bytes` to the buckets, the next chunk waits for the longer debt.
+    fn pay(&mut self, bytes: u64) {
+        if !self.limited || bytes == 0 {
+            return;
+        }
+        let options = &self.ctx.options;
+        let mut delay = Duration::ZERO;
+        if let Some(rate) = global_rate(options.limit_rate, &options.limit_schedule) {
+            let mut global = self
+                .ctx
+                .limiter
+                .0
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            delay = global.get_or_insert_with(Bucket::new).take(bytes, rate);
+        }
+        if let Some(rate) = options.limit_per_connection.filter(|rate| *rate > 0) {
+            delay = delay.max(self.connection.take(bytes, rate));
+        }
+        if !delay.is_zero() {
+            self.wait = Some(Box::pin(sleep(delay)));
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Throttled<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_wait(cx));
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - before) as u64;
+        self.pay(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Throttled<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_wait(cx));
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.pay(written as u64);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_wait(cx));
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
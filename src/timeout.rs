@@ -0,0 +1,332 @@
+use crate::{ftp::FtpStream, options::Connection, remote::RemoteEntry, transport::Transport};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, timeout, Instant},
+};
+
+/// Seconds of a timeout flag as a duration, `0` waits forever.
+fn limit(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// Error of a phase that did not finish in time.
+///
+/// It is an `io::ErrorKind::TimedOut`, so the connection is dropped and opened again
+/// like a lost one, a late reply would be taken as the answer of the next command.
+fn timed_out(message: String) -> anyhow::Error {
+    io::Error::new(io::ErrorKind::TimedOut, message).into()
+}
+
+/// Run `future` within `limit`, `phase` names it in the error.
+async fn deadline<T>(
+    limit: Option<Duration>,
+    phase: &str,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(limit) = limit else {
+        return future.await;
+    };
+    timeout(limit, future).await.unwrap_or_else(|_| {
+        Err(timed_out(format!(
+            "{} timed out after {}s",
+            phase,
+            limit.as_secs()
+        )))
+    })
+}
+
+/// Connect within `--connect-timeout`, handshakes of TLS and SSH included.
+pub async fn connect_within<T>(
+    connection: &Connection,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    deadline(limit(connection.connect_timeout), "connect", future).await
+}
+
+/// When data last moved through a transfer.
+struct Activity {
+    start: Instant,
+    /// Milliseconds from `start`.
+    last: AtomicU64,
+    /// The local reader or writer is waiting, like for `--limit-rate`, the server is
+    /// not stalled meanwhile.
+    local_wait: AtomicBool,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            local_wait: AtomicBool::new(false),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+        self.local_wait.store(false, Ordering::Relaxed);
+    }
+
+    /// Track a poll of the local side, the idle clock stops while it is pending.
+    fn poll<T>(&self, poll: Poll<T>) -> Poll<T> {
+        match poll {
+            Poll::Ready(_) => self.touch(),
+            Poll::Pending => self.local_wait.store(true, Ordering::Relaxed),
+        }
+        poll
+    }
+
+    fn idle(&self) -> Duration {
+        if self.local_wait.load(Ordering::Relaxed) {
+            return Duration::ZERO;
+        }
+        self.start
+            .elapsed()
+            .saturating_sub(Duration::from_millis(self.last.load(Ordering::Relaxed)))
+    }
+
+    /// Run a transfer until it ends or no data moved for `limit`.
+    ///
+    /// A blocked server stops the local reader or writer from being polled, so the
+    /// stall shows up here whichever side waits.
+    async fn watch<T>(
+        &self,
+        limit: Option<Duration>,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let Some(limit) = limit else {
+            return future.await;
+        };
+        tokio::pin!(future);
+        loop {
+            let wait = limit.saturating_sub(self.idle());
+            if wait.is_zero() {
+                return Err(timed_out(format!(
+                    "data transfer stalled, no data for {}s",
+                    limit.as_secs()
+                )));
+            }
+            tokio::select! {
+                result = &mut future => return result,
+                _ = sleep(wait) => {}
+            }
+        }
+    }
+}
+
+/// Local reader or writer of a transfer, touching its activity on every chunk.
+///
+/// The throttle of `--limit-rate` sleeps in the local side, that time is not idle.
+struct Watched<'a, T: ?Sized> {
+    inner: &'a mut T,
+    activity: &'a Activity,
+}
+
+impl<R: AsyncRead + Unpin + ?Sized> AsyncRead for Watched<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        self.activity.poll(poll)
+    }
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized> AsyncWrite for Watched<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        self.activity.poll(poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Connection with `--command-timeout` on every command and `--idle-timeout` on
+/// transfers.
+///
+/// FTP only commands through `as_ftp`, like `HASH` of a large file, have no deadline.
+pub struct Timed {
+    inner: Box<dyn Transport>,
+    command: Option<Duration>,
+    idle: Option<Duration>,
+}
+
+impl Timed {
+    pub fn new(inner: Box<dyn Transport>, connection: &Connection) -> Self {
+        Timed {
+            inner,
+            command: limit(connection.command_timeout),
+            idle: limit(connection.idle_timeout),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for Timed {
+    async fn login(&mut self, connection: &Connection) -> Result<()> {
+        deadline(self.command, "command login", self.inner.login(connection)).await
+    }
+
+    fn welcome(&self) -> Option<&str> {
+        self.inner.welcome()
+    }
+
+    async fn cwd(&mut self, path: &str) -> Result<()> {
+        deadline(self.command, "command cwd", self.inner.cwd(path)).await
+    }
+
+    async fn pwd(&mut self) -> Result<String> {
+        deadline(self.command, "command pwd", self.inner.pwd()).await
+    }
+
+    async fn mkdir(&mut self, path: &str) -> Result<()> {
+        deadline(self.command, "command mkdir", self.inner.mkdir(path)).await
+    }
+
+    async fn rm(&mut self, path: &str) -> Result<()> {
+        deadline(self.command, "command rm", self.inner.rm(path)).await
+    }
+
+    async fn rmdir(&mut self, path: &str) -> Result<()> {
+        deadline(self.command, "command rmdir", self.inner.rmdir(path)).await
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        deadline(self.command, "command rename", self.inner.rename(from, to)).await
+    }
+
+    async fn size(&mut self, path: &str) -> Result<u64> {
+        deadline(self.command, "command size", self.inner.size(path)).await
+    }
+
+    async fn mdtm(&mut self, path: &str) -> Result<DateTime<Utc>> {
+        deadline(self.command, "command mdtm", self.inner.mdtm(path)).await
+    }
+
+    async fn list_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>> {
+        deadline(self.command, "command list", self.inner.list_dir(path)).await
+    }
+
+    async fn set_binary(&mut self, binary: bool) -> Result<()> {
+        deadline(self.command, "command type", self.inner.set_binary(binary)).await
+    }
+
+    async fn put(
+        &mut self,
+        path: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        offset: u64,
+    ) -> Result<u64> {
+        let activity = Activity::new();
+        let mut reader = Watched {
+            inner: reader,
+            activity: &activity,
+        };
+        activity
+            .watch(self.idle, self.inner.put(path, &mut reader, offset))
+            .await
+    }
+
+    async fn get(
+        &mut self,
+        path: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<u64> {
+        let activity = Activity::new();
+        let mut writer = Watched {
+            inner: writer,
+            activity: &activity,
+        };
+        activity
+            .watch(self.idle, self.inner.get(path, &mut writer))
+            .await
+    }
+
+    async fn quit(&mut self) -> Result<()> {
+        deadline(self.command, "command quit", self.inner.quit()).await
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    fn as_ftp(&mut self) -> Option<&mut FtpStream> {
+        self.inner.as_ftp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Reader waiting longer than the idle limit before its data, like a throttle.
+    struct Slow(Option<Pin<Box<tokio::time::Sleep>>>);
+
+    impl AsyncRead for Slow {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if let Some(wait) = &mut self.0 {
+                std::task::ready!(wait.as_mut().poll(cx));
+                self.0 = None;
+                buf.put_slice(b"data");
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn local_wait_is_not_idle() {
+        let activity = Activity::new();
+        let mut slow = Slow(Some(Box::pin(sleep(Duration::from_millis(300)))));
+        let mut reader = Watched {
+            inner: &mut slow,
+            activity: &activity,
+        };
+        let mut data = vec![];
+        activity
+            .watch(Some(Duration::from_millis(100)), async {
+                reader.read_to_end(&mut data).await?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(data, b"data");
+
+        // The server side not polling the reader is still a stall.
+        let stalled = activity
+            .watch(Some(Duration::from_millis(100)), async {
+                sleep(Duration::from_millis(300)).await;
+                Ok(())
+            })
+            .await;
+        assert!(stalled.unwrap_err().to_string().contains("stalled"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    ftp::{self, FtpStream},
+    options::{Connection, Protocol},
+    remote::RemoteEntry,
+    sftp,
+    timeout::{connect_within, Timed},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Remote operations used by cymo, implemented by FTP and SFTP.
+///
+/// Relative paths are resolved against the current remote directory, just like FTP
+/// does, so callers can `cwd` into a directory and work with file names.
+#[async_trait]
+pub trait Transport: Send {
+    /// Authenticate with `--username`, `--password` or `--identity`.
+    async fn login(&mut self, connection: &Connection) -> Result<()>;
+
+    /// Message sent by server after connected.
+    fn welcome(&self) -> Option<&str> {
+        None
+    }
+
+    async fn cwd(&mut self, path: &str) -> Result<()>;
+
+    async fn pwd(&mut self) -> Result<String>;
+
+    async fn mkdir(&mut self, path: &str) -> Result<()>;
+
+    async fn rm(&mut self, path: &str) -> Result<()>;
+
+    async fn rmdir(&mut self, path: &str) -> Result<()>;
+
+    /// Rename a file, servers may refuse when `to` exists.
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()>;
+
+    async fn size(&mut self, path: &str) -> Result<u64>;
+
+    async fn mdtm(&mut self, path: &str) -> Result<DateTime<Utc>>;
+
+    /// List a directory, current and parent directory are not included.
+    async fn list_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>>;
+
+    /// Transfer following files as binary or text, only FTP has text mode.
+    async fn set_binary(&mut self, _binary: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write a remote file with everything from `reader`.
+    ///
+    /// When `offset` is not zero, the remote file already has `offset` bytes and
+    /// `reader` starts from there, the rest is appended.
+    ///
+    /// ## Return
+    ///
+    /// Bytes written.
+    async fn put(
+        &mut self,
+        path: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+        offset: u64,
+    ) -> Result<u64>;
+
+    /// Read a remote file into `writer`.
+    ///
+    /// ## Return
+    ///
+    /// Bytes read.
+    async fn get(
+        &mut self,
+        path: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<u64>;
+
+    async fn quit(&mut self) -> Result<()>;
+
+    /// Whether the connection is known to be closed, errors may not tell it.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// The FTP connection, for FTP only commands like `HASH`.
+    fn as_ftp(&mut self) -> Option<&mut FtpStream> {
+        None
+    }
+}
+
+/// Connect with the protocol from `--protocol` or the scheme of `--server`.
+///
+/// The connection has the deadlines of `--command-timeout` and `--idle-timeout`.
+pub async fn connect(connection: &Connection) -> Result<Box<dyn Transport>> {
+    let inner: Box<dyn Transport> = connect_within(connection, async {
+        Ok::<Box<dyn Transport>, _>(match connection.protocol() {
+            Protocol::Ftp => Box::new(ftp::connect(connection).await?),
+            Protocol::Sftp => Box::new(sftp::connect(connection).await?),
+        })
+    })
+    .await?;
+    Ok(Box::new(Timed::new(inner, connection)))
+}
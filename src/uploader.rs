@@ -0,0 +1,459 @@
+use crate::{
+    context::{Confirm, Context},
+    filter::Filter,
+    interrupt::{Stop, StopHandle},
+    journal::{read_list, remove_list, write_list, Journal},
+    mirror::mirror,
+    options::{Connection, Options, Protocol, TlsMode},
+    release::Release,
+    report::{Event, Events, UploadReport},
+    utils::{list_download_files, prepare_upload, transfer_files},
+};
+
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime;
+
+/// Upload a local directory or file to a server, many of them can run in one process.
+///
+/// Options not set keep the defaults of `cymo upload`. Nothing is printed, messages and
+/// progress are sent to the handler of `on_event`.
+///
+/// ```no_run
+/// use cymo::Uploader;
+///
+/// let report = Uploader::new("sftp://example.com", "./dist", "/var/www")
+///     .credentials("deploy", "secret")
+///     .threads(4)
+///     .on_event(|event| eprintln!("{:?}", event))
+///     .run()?;
+/// println!("{} file(s) failed", report.failed);
+/// # anyhow::Ok(())
+/// ```
+pub struct Uploader {
+    options: Options,
+    events: Events,
+    stop: Arc<Stop>,
+    confirm: Option<Confirm>,
+}
+
+impl Uploader {
+    /// Upload `local_path` into `remote_path` on `server`, which may have a scheme and port.
+    pub fn new(server: &str, local_path: &str, remote_path: &str) -> Self {
+        Self::with_options(Options {
+            remote_path: remote_path.to_string(),
+            local_path: local_path.to_string(),
+            connection: Connection {
+                server: server.to_string(),
+                ..Connection::default()
+            },
+            ..Options::default()
+        })
+    }
+
+    /// Run with all options given, like the ones of `upload`, `sync` or `download`.
+    pub fn with_options(options: Options) -> Self {
+        Uploader {
+            options,
+            events: Events::default(),
+            stop: Arc::default(),
+            confirm: None,
+        }
+    }
+
+    pub fn port(mut self, port: u32) -> Self {
+        self.options.connection.port = Some(port);
+        self
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.options.connection.protocol = Some(protocol);
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsMode) -> Self {
+        self.options.connection.tls = tls;
+        self
+    }
+
+    /// Accept invalid server certificates or unknown SSH host keys.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.options.connection.insecure = insecure;
+        self
+    }
+
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        self.options.connection.username = Some(username.to_string());
+        self.options.connection.password = Some(password.to_string());
+        self
+    }
+
+    /// Private key for SFTP, the password of `credentials` is its passphrase when encrypted.
+    pub fn identity(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.connection.identity = Some(path.into());
+        self
+    }
+
+    /// Connections transferring at the same time, defaults to the CPU count.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.connection.thread = Some(threads);
+        self
+    }
+
+    /// Attempts of a failed file after the first one.
+    pub fn retry(mut self, times: u32) -> Self {
+        self.options.connection.retry = Some(times);
+        self
+    }
+
+    /// Wait `delay` before the first retry, growing by `multiplier` up to `max`.
+    pub fn backoff(mut self, delay: Duration, multiplier: f64, max: Duration) -> Self {
+        self.options.connection.retry_delay = delay.as_secs_f64();
+        self.options.connection.retry_multiplier = multiplier;
+        self.options.connection.retry_max_delay = max.as_secs_f64();
+        self
+    }
+
+    /// Continue partially uploaded files from the size already on server.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.options.resume = resume;
+        self
+    }
+
+    /// Compare each uploaded file with the local one, mismatches are retried.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    /// Upload to a temporary name, renamed to the final name when finished.
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.options.atomic = atomic;
+        self
+    }
+
+    /// Write pending and failed files here when stopped, for `--resume-journal`.
+    pub fn journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.journal = Some(path.into());
+        self
+    }
+
+    /// Write failed files here, one per line, for `--files-from`.
+    pub fn failed_list(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.failed_list = Some(path.into());
+        self
+    }
+
+    /// Handle events of the run, called from its threads as they happen.
+    pub fn on_event(mut self, handler: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        self.events = Events::new(handler);
+        self
+    }
+
+    /// Send events of the run into a channel, events after the receiver is dropped are lost.
+    pub fn event_sender(self, sender: mpsc::Sender<Event>) -> Self {
+        self.on_event(move |event| {
+            let _ = sender.send(event);
+        })
+    }
+
+    /// Ask before `--delete` removes remote entries, `false` cancels the deletion.
+    pub fn confirm_delete(
+        mut self,
+        confirm: impl Fn(usize) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.confirm = Some(Box::new(confirm));
+        self
+    }
+
+    /// Stop the run from another thread.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle(self.stop.clone())
+    }
+
+    /// Transfer all files on a new multi-threaded runtime, blocking until finished.
+    ///
+    /// Failed files do not fail the run, they are counted in the report. Errors are
+    /// returned only when the run could not start or finish, like a wrong password.
+    pub fn run(self) -> Result<UploadReport> {
+        runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(self.run_async())
+    }
+
+    /// Same as `run` on the runtime of the caller, files are transferred by tasks spawned on it.
+    pub async fn run_async(self) -> Result<UploadReport> {
+        let Uploader {
+            mut options,
+            events,
+            stop,
+            confirm,
+        } = self;
+        let started = Instant::now();
+        if options.release && options.delete {
+            // A new release only has the uploaded files, and it is renamed before mirroring.
+            return Err(anyhow!(
+                "--delete can not be used with --release, a release has no remote entries to delete"
+            ));
+        }
+        // Files go into the new release, the live path is only swapped at the end.
+        let live_path = options.remote_path.clone();
+        let previous = match &options.resume_journal {
+            Some(path) => Some(read_journal(path, &options)?),
+            None => None,
+        };
+        let release = if options.release {
+            let release =
+                Release::create(&options.connection, &options.remote_path, &events).await?;
+            options.remote_path = release.upload_path();
+            events.info(format!("Uploading release into {}", options.remote_path));
+            Some(release)
+        } else {
+            None
+        };
+        let ctx = Arc::new(Context::new(options, events, stop, confirm));
+        let options = &ctx.options;
+        // Found files
+        let mut files = if options.download {
+            list_download_files(&ctx).await?
+        } else if options.files_from.is_some() {
+            // Listed files are uploaded even when filters exclude them.
+            vec![]
+        } else {
+            Filter::new(options, &ctx.events)?
+                .walk()
+                .map(|e| PathBuf::from(e.path()))
+                .filter(|e| e.is_file())
+                .collect::<Vec<_>>()
+        };
+        if let Some(source) = &options.files_from {
+            files = listed_files(&ctx, source, files)?;
+        }
+        if let (Some(previous), Some(path)) = (&previous, &options.resume_journal) {
+            let remaining = previous.remaining();
+            files.retain(|file| remaining.contains(file));
+            ctx.info(format!("Continue {} file(s) of {:?}", files.len(), path));
+        }
+        files.sort_by_key(|a| a.components().count());
+        let files_count = files.len();
+        // Remote file sizes are only known when they start.
+        let bytes = (!options.download).then(|| {
+            files
+                .iter()
+                .filter_map(|file| file.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum()
+        });
+        ctx.emit(Event::Started {
+            files: files_count,
+            bytes,
+        });
+
+        let (files, skipped) = if options.download {
+            // Local directories are created while listing.
+            (files, 0)
+        } else {
+            prepare_upload(&ctx, files).await?
+        };
+        let connections = options
+            .connection
+            .thread
+            .unwrap_or(thread::available_parallelism()?.get());
+        let mut journal = Journal {
+            action: options.action().to_string(),
+            local_path: options.local_path.clone(),
+            remote_path: live_path.clone(),
+            ..transfer_files(ctx.clone(), files, connections).await?
+        };
+        ctx.emit(Event::Finished);
+
+        let failed_files = journal.failed.clone();
+        let count = journal.completed.len();
+        let pending = journal.pending.len();
+        ctx.info(format!(
+            "Total find {} file(s) {} {} file(s), {} file(s) skipped, {} file(s) failed",
+            files_count,
+            options.action(),
+            count,
+            skipped,
+            failed_files.len()
+        ));
+        let (connection_attempts, reconnects) = ctx.connection_stats();
+        ctx.info(format!(
+            "Total {} connection attempt(s), {} reconnection(s)",
+            connection_attempts, reconnects
+        ));
+        if let Some(path) = options.failed_list.as_deref() {
+            if failed_files.is_empty() {
+                // A list left by an earlier run would retry files already done.
+                remove_list(path)?;
+            } else {
+                write_list(path, &failed_files)?;
+                ctx.warn(format!(
+                    "Failed files written into {:?}, retry them with --files-from",
+                    path
+                ));
+            }
+        }
+        let interrupted = ctx.stop.is_stopping();
+        if let (true, Some(path)) = (interrupted, options.journal.as_deref()) {
+            // Files done by previous runs are kept for the next resume.
+            if let Some(previous) = previous {
+                journal.completed.splice(0..0, previous.completed);
+            }
+            journal.write(path)?;
+            ctx.warn(format!(
+                "Interrupted with {} file(s) pending, continue with --resume-journal {:?}",
+                pending, path
+            ));
+        } else if let (false, Some(previous), Some(path)) =
+            (interrupted, previous, &options.resume_journal)
+        {
+            // Resuming the journal again must not transfer files already done.
+            if failed_files.is_empty() {
+                Journal::remove(path)?;
+                ctx.info(format!("All files of {:?} done, journal removed", path));
+            } else {
+                journal.completed.splice(0..0, previous.completed);
+                journal.write(path)?;
+                ctx.warn(format!(
+                    "{} file(s) failed again, still in {:?}",
+                    failed_files.len(),
+                    path
+                ));
+            }
+        }
+        if let Some(release) = &release {
+            if failed_files.is_empty() && !interrupted {
+                release
+                    .publish(&options.connection, options.keep_releases)
+                    .await?;
+            } else {
+                ctx.warn(format!("Release not published, {} is untouched", live_path));
+            }
+        }
+        let deleted = if options.delete && interrupted {
+            ctx.warn("Interrupted, remote entries are not deleted");
+            None
+        } else if options.delete {
+            let deleted = mirror(&ctx).await?;
+            ctx.info(format!("Total delete {} remote entries", deleted));
+            Some(deleted)
+        } else {
+            None
+        };
+
+        let report = UploadReport {
+            action: options.action(),
+            server: options.connection.addr(),
+            local_path: options.local_path.clone(),
+            remote_path: live_path,
+            found: files_count,
+            transferred: count,
+            skipped,
+            failed: failed_files.len(),
+            failed_files,
+            pending,
+            interrupted,
+            connection_attempts,
+            reconnects,
+            deleted,
+            bytes: ctx.sent(),
+            duration_ms: started.elapsed().as_millis(),
+        };
+        ctx.emit(Event::Summary(report.clone()));
+        if let Some(path) = &options.report {
+            report.write(path)?;
+        }
+        Ok(report)
+    }
+}
+
+/// Files of `--files-from`.
+///
+/// Uploads use the listed local files, downloads keep the listed ones of the `found`
+/// remote files.
+fn listed_files(ctx: &Context, source: &Path, found: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let options = &ctx.options;
+    if options.release {
+        return Err(anyhow!(
+            "--files-from can not be used with --release, the release would miss files"
+        ));
+    }
+    if options.download {
+        let listed = read_list(source, &options.remote_path)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let files = found
+            .into_iter()
+            .filter(|file| listed.contains(file))
+            .collect::<Vec<_>>();
+        if files.len() < listed.len() {
+            ctx.warn(format!(
+                "{} file(s) in {:?} not found on server",
+                listed.len() - files.len(),
+                source
+            ));
+        }
+        return Ok(files);
+    }
+    let mut listed = HashSet::new();
+    let mut files = read_list(source, &options.local_path)?;
+    files.retain(|file| {
+        if !file.is_file() {
+            ctx.warn(format!(
+                "{:?} in {:?} is not a local file, skipped",
+                file, source
+            ));
+            return false;
+        }
+        listed.insert(file.clone())
+    });
+    Ok(files)
+}
+
+/// Read the journal of `--resume-journal`, it must be from the same transfer.
+fn read_journal(path: &Path, options: &Options) -> Result<Journal> {
+    let journal = Journal::read(path)?;
+    if options.release {
+        return Err(anyhow!(
+            "--resume-journal can not be used with --release, the release would miss files"
+        ));
+    }
+    if journal.action != options.action()
+        || journal.local_path != options.local_path
+        || journal.remote_path != options.remote_path
+    {
+        return Err(anyhow!(
+            "Journal {:?} is of {} {} to {}, not this transfer",
+            path,
+            journal.action,
+            journal.local_path,
+            journal.remote_path
+        ));
+    }
+    Ok(journal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_with_delete() {
+        let uploader = Uploader::with_options(Options {
+            release: true,
+            delete: true,
+            ..Options::default()
+        });
+        let err = uploader.run().unwrap_err();
+        assert!(err.to_string().contains("--delete"), "{}", err);
+    }
+}
@@ -1,16 +1,21 @@
 use crate::{
-    args::Args,
-    eudora::{connect_and_init, get_args, remote_mkdir, upload},
+    context::Context,
+    eudora::{local_file, remote_mkdir, transfer},
+    journal::Journal,
+    options::Options,
+    reconnect::connect_session,
+    remote::list_tree,
+    report::Event,
+    skip::filter_up_to_date,
+    transport::Transport,
 };
-use anyhow::{anyhow, Ok as AOk, Result};
-use crossbeam_channel::{Receiver, Sender};
+use anyhow::Result;
 use std::{
     path::PathBuf,
     sync::{Arc, Mutex},
-    thread::{self, JoinHandle},
+    time::Instant,
 };
-use suppaftp::AsyncFtpStream;
-use tokio::runtime;
+use tokio::task::JoinSet;
 
 /// Find parents of all files
 ///
@@ -55,176 +60,215 @@ pub fn fold_parents(local_path: &String) -> impl Fn(Vec<PathBuf>, &PathBuf) -> V
     }
 }
 
-/// In a single system thread to parse files.
-///
-/// - connect to ftp server and create all parents not exist on server.
-/// - divide file list by cpu nums, then send to child threads.
+/// Create parents of all files on server, and drop files already up to date.
 ///
 /// ## Arguments
 ///
+/// - `ctx`: state of the run.
 /// - `files`: total found files path.
-/// - `cpus`: current cpu nums.
-/// - `sneder`: message channel for send files.
+///
+/// ## Return
+///
+/// Files to be uploaded, and the count of files skipped by `--skip-existing`.
 ///
 /// ## Error
 ///
-/// The function will failure when create parent folders on ftp server.
-pub fn build_worker_task(
-    mut files: Vec<PathBuf>,
-    cpus: usize,
-    sender: Sender<Vec<PathBuf>>,
-) -> impl FnMut() {
-    move || {
-        let rt = runtime::Builder::new_current_thread().build().unwrap();
-        let task = async {
-            let Args {
-                server,
-                port,
-                local_path,
-                remote_path,
-                ..
-            } = get_args()?;
-            let addr = format!("{}:{}", server, port);
-            let mut ftp_stream = AsyncFtpStream::connect(addr).await.map_err(|err| {
-                eprintln!("Thread main connnect failed {}", err);
-                anyhow!("{}", err)
-            });
-            let _ = connect_and_init(ftp_stream.as_mut(), 0).await;
-            let mut ftp_stream = ftp_stream?;
+/// The function will failure when login or create parent folders on ftp server.
+pub async fn prepare_upload(ctx: &Context, files: Vec<PathBuf>) -> Result<(Vec<PathBuf>, usize)> {
+    let Options {
+        local_path,
+        remote_path,
+        skip_existing,
+        ..
+    } = &ctx.options;
+    let mut ftp_stream = connect_session(ctx, 0, &mut 0).await?;
 
-            // All element in files is files, so can use parent.
-            // Create all parent folders.
-            let all_parents: Vec<_> = files.iter().fold(vec![], fold_parents(local_path));
-            for parent in all_parents {
-                let mut remote = PathBuf::from(&remote_path);
-                remote.push(parent);
-                remote_mkdir(&mut ftp_stream, 0, &remote.to_string_lossy()).await?;
-            }
-            // Total files length
-            let len = files.len();
-            let (quotient, remainder) = (len / cpus, len % cpus); // calculate the quotient and remainders.send()
-            let start = 0;
-            let sender = (0..cpus)
-                .map(|i| {
-                    let end = if i < remainder {
-                        // if i is less than the remainder, add one extra element to the smaller array
-                        start + quotient + 1
-                    } else {
-                        // otherwise, use the quotient as the size of the smaller array
-                        start + quotient
-                    };
-                    let file_data = files.drain(start..end).collect::<Vec<_>>();
-                    sender.send(file_data)?;
-                    AOk(())
-                })
-                .collect::<Result<Vec<_>>>();
-            AOk((sender, len))
-        };
-        let (result, len) = rt.block_on(task).expect("start a tokio runtime failed");
-        match result {
-            // TODO retry, when files is empty, exit threads
-            Ok(_) => {
-                println!("Total send {} files", len);
-            }
-            Err(err) => {
-                eprintln!(
-                    "Send files to thread failed {:?}. {} files not send",
-                    err, len
-                );
-            }
-        };
+    // All element in files is files, so can use parent.
+    // Create all parent folders.
+    let all_parents: Vec<_> = files.iter().fold(vec![], fold_parents(local_path));
+    for parent in all_parents {
+        let mut remote = PathBuf::from(&remote_path);
+        remote.push(parent);
+        remote_mkdir(ctx, ftp_stream.as_mut(), 0, &remote.to_string_lossy()).await?;
     }
+    // Only send files changed since last upload.
+    let prepared = match skip_existing {
+        Some(policy) => filter_up_to_date(ctx, ftp_stream.as_mut(), files, *policy).await?,
+        None => (files, 0),
+    };
+    let _ = ftp_stream.quit().await;
+    Ok(prepared)
 }
 
-/// Build upload threads.
+/// Find all files to be downloaded.
 ///
-/// This function will be build tokio async runtime in single
-/// thread. And connect to ftp server in the runtime.
+/// - connect to ftp server and walk `--remote-path` recursively, hidden entries only
+///   with `--hidden`, or take it as the only file when it is not a directory.
+/// - create all local directories for the remote ones.
 ///
-/// ## Arguments
+/// ## Return
+///
+/// Full remote paths of all files.
+pub async fn list_download_files(ctx: &Context) -> Result<Vec<PathBuf>> {
+    let Options {
+        remote_path,
+        hidden,
+        ..
+    } = &ctx.options;
+    let mut ftp_stream = connect_session(ctx, 0, &mut 0).await?;
+
+    let home = ftp_stream.pwd().await?;
+    let is_dir = ftp_stream.cwd(remote_path).await.is_ok();
+    ftp_stream.cwd(&home).await?;
+    let (files, dirs) = if is_dir {
+        list_tree(ftp_stream.as_mut(), &PathBuf::from(remote_path), *hidden).await?
+    } else {
+        // Fails with the error of the server when the file does not exist either.
+        ftp_stream.set_binary(true).await?;
+        ftp_stream.size(remote_path).await?;
+        (vec![PathBuf::from(remote_path)], vec![])
+    };
+    tokio::fs::create_dir_all(ctx.local_root()).await?;
+    for dir in dirs {
+        let local = local_file(ctx, &dir);
+        tokio::fs::create_dir_all(&local).await?;
+        ctx.info(format!("Thread 0 create directory {:?} success", local));
+        ctx.emit(Event::Mkdir {
+            thread: 0,
+            path: local.to_string_lossy().to_string(),
+        });
+    }
+    ftp_stream.quit().await?;
+    Ok(files)
+}
+
+/// Connection of a worker, reused by the files it transfers.
+struct Worker {
+    thread: usize,
+    /// Without a connection the next file connects again before it starts.
+    session: Option<Box<dyn Transport>>,
+    completed: usize,
+}
+
+/// Files no worker has taken yet.
+type Queue = Arc<Mutex<std::vec::IntoIter<PathBuf>>>;
+
+/// Transfer files over at most `connections` connections at the same time.
+///
+/// Every connection is a worker task on the runtime taking the next file from a shared
+/// queue until it is empty, so only `connections` files are in progress and connections
+/// are opened once. No more connections are opened than there are files.
 ///
-/// - `receiver`: file list receiver.
-/// - `file_count`: file list length.
-/// - `failed_files`: file list for sent failed.
+/// After `StopHandle::stop` the rest of the files are recorded as pending without transfer,
+/// `StopHandle::abort` cancels files in progress.
 ///
 /// ## Return
 ///
-/// A std thread handler `JoinHandle<()>`.
-pub fn create_thread_task(
-    receiver: Receiver<Vec<PathBuf>>,
-    file_count: Arc<Mutex<usize>>,
-    failed_files: Arc<Mutex<Vec<PathBuf>>>,
-) -> impl Fn(usize) -> JoinHandle<()> {
-    move |i| {
-        let r = receiver.clone();
-        let file_count = file_count.clone();
-        let failed_files = failed_files.clone();
-        let thread_task = move || {
-            let rt = runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("create tokio runtime failed");
-
-            let async_task = async {
-                let Args { server, port, .. } = get_args()?;
-                let addr = format!("{}:{}", server, port);
-                println!("Thread {} connecting {}", i, &addr);
-                // TODO read username and password in environment
-                let mut ftp_stream = AsyncFtpStream::connect(addr).await.map_err(|err| {
-                    eprintln!("Thread {} connnect failed {}", i, err);
-                    anyhow!("{}", err)
-                });
-                let _ = connect_and_init(ftp_stream.as_mut(), i).await;
+/// Files by their result.
+pub async fn transfer_files(
+    ctx: Arc<Context>,
+    files: Vec<PathBuf>,
+    connections: usize,
+) -> Result<Journal> {
+    let mut journal = Journal::default();
+    let connections = connections.min(files.len());
+    let queue: Queue = Arc::new(Mutex::new(files.into_iter()));
+    let mut tasks = JoinSet::new();
+    for i in 1..=connections {
+        tasks.spawn(run_worker(ctx.clone(), queue.clone(), i));
+    }
+    while let Some(finished) = tasks.join_next().await {
+        let worker = finished?;
+        journal.completed.extend(worker.completed);
+        journal.failed.extend(worker.failed);
+        journal.pending.extend(worker.pending);
+    }
+    // Files left when stopped.
+    journal
+        .pending
+        .extend(queue.lock().unwrap_or_else(|err| err.into_inner()).by_ref());
+    Ok(journal)
+}
 
-                let mut current_failed = vec![];
-                // Receive files from main thread.
-                let mut thread_count = 0_usize;
-                for (count, path) in (r.recv()?).into_iter().enumerate() {
-                    let ftp_stream = if let Ok(stream) = ftp_stream.as_mut() {
-                        stream
-                    } else {
-                        current_failed.push(path);
-                        continue;
-                    };
-                    match upload(ftp_stream, i, &path, 0).await {
-                        Ok(_) => {
-                            thread_count = count + 1;
-                        }
-                        Err(err) => {
-                            eprintln!("Thread {} upload {:?} failed, {}", i, path, err);
-                            current_failed.push(path);
-                        }
-                    }
-                }
-                if thread_count != 0 {
-                    file_count
-                        .lock()
-                        .map(|mut file_count| {
-                            *file_count += thread_count;
-                            println!("Thread {} uploaded {} files", i, thread_count);
-                        })
-                        .map_err(|err| anyhow!("Thread {} write file cout failed {}", i, err))?;
-                }
-                if !current_failed.is_empty() {
-                    failed_files
-                        .lock()
-                        .map(|mut failed_files| {
-                            failed_files.append(&mut current_failed);
-                        })
-                        .map_err(|err| {
-                            anyhow!("Thread {} collect failed files failed {}", i, err)
-                        })?;
-                }
-                println!("Thread {} exiting", i);
-                ftp_stream?.quit().await?;
-                AOk(())
-            };
-            let async_handle = rt.block_on(async_task);
-            if let Err(err) = async_handle {
-                eprintln!("Thread {} got error {}", i, err);
-            };
+/// Connect, then transfer files from the queue until it is empty or stopped.
+///
+/// A task per connection instead of per file, spawning a task and passing the
+/// connection around for every file was slower with many small files.
+async fn run_worker(ctx: Arc<Context>, queue: Queue, i: usize) -> Journal {
+    let mut journal = Journal::default();
+    let mut worker = connect_worker(&ctx, i).await;
+    while !ctx.stop.is_stopping() {
+        let Some(path) = queue.lock().unwrap_or_else(|err| err.into_inner()).next() else {
+            break;
         };
+        let finished = transfer_file(&ctx, &mut worker, path).await;
+        record(&mut journal, finished);
+    }
+
+    let action = ctx.options.action();
+    if worker.completed > 0 {
+        ctx.info(format!(
+            "Thread {} {}ed {} files",
+            worker.thread, action, worker.completed
+        ));
+    }
+    ctx.info(format!("Thread {} exiting", worker.thread));
+    // An aborted transfer leaves the connection in an unknown state.
+    if let (Some(mut ftp_stream), false) = (worker.session, ctx.stop.is_aborted()) {
+        if let Err(err) = ftp_stream.quit().await {
+            ctx.warn(format!("Thread {} got error {}", worker.thread, err));
+        }
+    }
+    journal
+}
+
+async fn connect_worker(ctx: &Context, i: usize) -> Worker {
+    ctx.info(format!(
+        "Thread {} connecting {}",
+        i,
+        ctx.options.connection.addr()
+    ));
+    let session = connect_session(ctx, i, &mut 0)
+        .await
+        .map_err(|err| ctx.warn(format!("Thread {} connect failed {}", i, err)))
+        .ok();
+    Worker {
+        thread: i,
+        session,
+        completed: 0,
+    }
+}
+
+/// Result of a file, `None` when aborted.
+type Finished = (PathBuf, Option<Result<()>>);
+
+/// Transfer a file on the connection of the worker.
+async fn transfer_file(ctx: &Context, worker: &mut Worker, path: PathBuf) -> Finished {
+    let i = worker.thread;
+    let action = ctx.options.action();
+    let started = Instant::now();
+    let result = tokio::select! {
+        result = transfer(ctx, &mut worker.session, i, &path) => Some(result),
+        _ = ctx.stop.aborted() => None,
+    };
+    match &result {
+        None => ctx.warn(format!("Thread {} {} {:?} aborted", i, action, path)),
+        Some(Ok(_)) => worker.completed += 1,
+        Some(Err(err)) => {
+            ctx.warn(format!(
+                "Thread {} {} {:?} failed, {}",
+                i, action, path, err
+            ));
+            ctx.fail(i, &path, err, started.elapsed());
+        }
+    }
+    (path, result)
+}
 
-        thread::spawn(thread_task)
+fn record(journal: &mut Journal, (path, result): Finished) {
+    match result {
+        None => journal.pending.push(path),
+        Some(Ok(_)) => journal.completed.push(path),
+        Some(Err(_)) => journal.failed.push(path),
     }
 }
@@ -0,0 +1,447 @@
+//! Transfers against an SFTP server started by the test, serving a temporary directory.
+
+use cymo::{
+    ls, mkdir,
+    options::{Connection, Options},
+    rm, Events, Uploader,
+};
+use russh::{
+    keys::{ssh_key::rand_core::OsRng, Algorithm, PrivateKey},
+    server::{Auth, Config, Msg, Server, Session},
+    Channel, ChannelId,
+};
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+};
+use tokio::{net::TcpListener, runtime};
+
+const USER: &str = "cymo";
+const PASSWORD: &str = "secret";
+
+/// Serve `root` on a local port in a thread of its own, the connection to it.
+fn start_server(root: &Path) -> Connection {
+    let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+    let config = Arc::new(Config {
+        keys: vec![key],
+        ..Config::default()
+    });
+    let mut server = SftpServer {
+        root: root.to_path_buf(),
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let rt = runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            sender.send(listener.local_addr().unwrap().port()).unwrap();
+            server.run_on_socket(config, &listener).await.unwrap();
+        });
+    });
+    Connection {
+        server: format!("sftp://127.0.0.1:{}", receiver.recv().unwrap()),
+        username: Some(USER.to_string()),
+        password: Some(PASSWORD.to_string()),
+        // The host key is new for every run.
+        insecure: true,
+        thread: Some(2),
+        ..Connection::default()
+    }
+}
+
+fn write(root: &Path, path: &str, content: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn upload_and_download() {
+    let served = tempfile::tempdir().unwrap();
+    let connection = start_server(served.path());
+    let events = Events::default();
+    mkdir(&connection, &["/www/site".to_string()], true, &events).unwrap();
+
+    let local = tempfile::tempdir().unwrap();
+    write(local.path(), "index.html", "<html></html>");
+    write(local.path(), "assets/app.js", "console.log(1)");
+    write(local.path(), "assets/img/logo.svg", "<svg/>");
+    write(local.path(), ".env", "SECRET=1");
+    let report = Uploader::with_options(Options {
+        local_path: local.path().to_string_lossy().to_string(),
+        remote_path: "/www/site".to_string(),
+        connection: connection.clone(),
+        verify: true,
+        ..Options::default()
+    })
+    .run()
+    .unwrap();
+    assert_eq!((report.found, report.transferred, report.failed), (3, 3, 0));
+    for path in ["index.html", "assets/app.js", "assets/img/logo.svg"] {
+        assert_eq!(
+            fs::read(served.path().join("www/site").join(path)).unwrap(),
+            fs::read(local.path().join(path)).unwrap(),
+            "{}",
+            path
+        );
+    }
+    assert!(!served.path().join("www/site/.env").exists());
+
+    let entries = ls(&connection, "/www/site", &events)
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.name, entry.is_dir, entry.size))
+        .collect::<Vec<_>>();
+    assert_eq!(entries[0].0, "assets");
+    assert!(entries[0].1);
+    assert_eq!(entries[1], ("index.html".to_string(), false, Some(13)));
+
+    let downloaded = tempfile::tempdir().unwrap();
+    let report = Uploader::with_options(Options {
+        local_path: downloaded.path().to_string_lossy().to_string(),
+        remote_path: "/www/site".to_string(),
+        connection: connection.clone(),
+        download: true,
+        ..Options::default()
+    })
+    .run()
+    .unwrap();
+    assert_eq!((report.found, report.transferred, report.failed), (3, 3, 0));
+    for path in ["index.html", "assets/app.js", "assets/img/logo.svg"] {
+        assert_eq!(
+            fs::read(downloaded.path().join(path)).unwrap(),
+            fs::read(local.path().join(path)).unwrap(),
+            "{}",
+            path
+        );
+    }
+
+    rm(&connection, &["/www/site".to_string()], true, &events).unwrap();
+    assert!(!served.path().join("www/site").exists());
+}
+
+#[test]
+fn wrong_password() {
+    let served = tempfile::tempdir().unwrap();
+    let connection = Connection {
+        password: Some("wrong".to_string()),
+        reconnect: 3,
+        ..start_server(served.path())
+    };
+    let local = tempfile::tempdir().unwrap();
+    write(local.path(), "index.html", "<html></html>");
+    let report = Uploader::with_options(Options {
+        local_path: local.path().to_string_lossy().to_string(),
+        remote_path: "/".to_string(),
+        connection,
+        ..Options::default()
+    })
+    .run();
+    // Authentication errors are permanent, the run fails without reconnecting.
+    assert!(report.is_err());
+}
+
+/// SSH server of the test, only the `sftp` subsystem is served.
+struct SftpServer {
+    root: PathBuf,
+}
+
+impl Server for SftpServer {
+    type Handler = SshSession;
+
+    fn new_client(&mut self, _: Option<SocketAddr>) -> SshSession {
+        SshSession {
+            root: self.root.clone(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+struct SshSession {
+    root: PathBuf,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl russh::server::Handler for SshSession {
+    type Error = russh::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        Ok(if user == USER && password == PASSWORD {
+            Auth::Accept
+        } else {
+            Auth::reject()
+        })
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        _: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        Ok(true)
+    }
+
+    async fn channel_eof(
+        &mut self,
+        id: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.close(id)
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        id: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        match (name, self.channels.remove(&id)) {
+            ("sftp", Some(channel)) => {
+                session.channel_success(id)?;
+                let sftp = Sftp {
+                    root: self.root.clone(),
+                    files: HashMap::new(),
+                    dirs: HashMap::new(),
+                    next: 0,
+                };
+                russh_sftp::server::run(channel.into_stream(), sftp).await;
+            }
+            _ => session.channel_failure(id)?,
+        }
+        Ok(())
+    }
+}
+
+/// SFTP requests on files under `root`, like a chroot.
+struct Sftp {
+    root: PathBuf,
+    files: HashMap<String, fs::File>,
+    /// Entries of an open directory, `None` after they are read.
+    dirs: HashMap<String, Option<Vec<File>>>,
+    next: u64,
+}
+
+impl Sftp {
+    /// Remote path normalized without `..`, always absolute.
+    fn normalize(path: &str) -> String {
+        let mut parts = vec![];
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => parts.push(name.to_string_lossy().to_string()),
+                Component::ParentDir => {
+                    parts.pop();
+                }
+                _ => {}
+            }
+        }
+        format!("/{}", parts.join("/"))
+    }
+
+    fn local(&self, path: &str) -> PathBuf {
+        self.root.join(&Self::normalize(path)[1..])
+    }
+
+    fn handle(&mut self) -> String {
+        self.next += 1;
+        self.next.to_string()
+    }
+
+    fn file(&mut self, handle: &str) -> Result<&mut fs::File, StatusCode> {
+        self.files.get_mut(handle).ok_or(StatusCode::Failure)
+    }
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en".to_string(),
+    }
+}
+
+fn status(err: io::Error) -> StatusCode {
+    match err.kind() {
+        io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    }
+}
+
+impl russh_sftp::server::Handler for Sftp {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> StatusCode {
+        StatusCode::OpUnsupported
+    }
+
+    async fn init(&mut self, _: u32, _: HashMap<String, String>) -> Result<Version, StatusCode> {
+        Ok(Version::new())
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        flags: OpenFlags,
+        _: FileAttributes,
+    ) -> Result<Handle, StatusCode> {
+        let file = fs::OpenOptions::new()
+            .read(flags.contains(OpenFlags::READ))
+            .write(flags.contains(OpenFlags::WRITE))
+            .append(flags.contains(OpenFlags::APPEND))
+            .create(flags.contains(OpenFlags::CREATE))
+            .truncate(flags.contains(OpenFlags::TRUNCATE))
+            .open(self.local(&filename))
+            .map_err(status)?;
+        let handle = self.handle();
+        self.files.insert(handle.clone(), file);
+        Ok(Handle { id, handle })
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, StatusCode> {
+        self.files.remove(&handle);
+        self.dirs.remove(&handle);
+        Ok(ok(id))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, StatusCode> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset)).map_err(status)?;
+        let mut data = vec![0; len as usize];
+        let read = file.read(&mut data).map_err(status)?;
+        if read == 0 {
+            return Err(StatusCode::Eof);
+        }
+        data.truncate(read);
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, StatusCode> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset)).map_err(status)?;
+        file.write_all(&data).map_err(status)?;
+        Ok(ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, StatusCode> {
+        let metadata = fs::symlink_metadata(self.local(&path)).map_err(status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, StatusCode> {
+        let metadata = fs::metadata(self.local(&path)).map_err(status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, StatusCode> {
+        let metadata = self.file(&handle)?.metadata().map_err(status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        _: String,
+        _: FileAttributes,
+    ) -> Result<Status, StatusCode> {
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        _: String,
+        _: FileAttributes,
+    ) -> Result<Status, StatusCode> {
+        Ok(ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, StatusCode> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(self.local(&path)).map_err(status)? {
+            let entry = entry.map_err(status)?;
+            let metadata = entry.metadata().map_err(status)?;
+            entries.push(File::new(
+                entry.file_name().to_string_lossy(),
+                FileAttributes::from(&metadata),
+            ));
+        }
+        let handle = self.handle();
+        self.dirs.insert(handle.clone(), Some(entries));
+        Ok(Handle { id, handle })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, StatusCode> {
+        match self.dirs.get_mut(&handle).and_then(Option::take) {
+            Some(files) => Ok(Name { id, files }),
+            None => Err(StatusCode::Eof),
+        }
+    }
+
+    async fn remove(&mut self, id: u32, path: String) -> Result<Status, StatusCode> {
+        fs::remove_file(self.local(&path)).map_err(status)?;
+        Ok(ok(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        _: FileAttributes,
+    ) -> Result<Status, StatusCode> {
+        fs::create_dir(self.local(&path)).map_err(status)?;
+        Ok(ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, StatusCode> {
+        fs::remove_dir(self.local(&path)).map_err(status)?;
+        Ok(ok(id))
+    }
+
+    async fn rename(&mut self, id: u32, from: String, to: String) -> Result<Status, StatusCode> {
+        fs::rename(self.local(&from), self.local(&to)).map_err(status)?;
+        Ok(ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, StatusCode> {
+        let path = Self::normalize(&path);
+        if !self.local(&path).exists() {
+            return Err(StatusCode::NoSuchFile);
+        }
+        Ok(Name {
+            id,
+            files: vec![File::dummy(path)],
+        })
+    }
+}